@@ -0,0 +1,142 @@
+//! Access to the images inside ZIP archives (like comic book archives) as if the archives were
+//! directories. A file inside an archive is identified by the path of the archive joined with
+//! the name of the file within the archive, for example `comics/issue.cbz/page01.jpg`.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+pub const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "cbz"];
+
+pub fn is_archive(path: &Path) -> bool {
+	has_archive_extension(path) && path.is_file()
+}
+
+fn has_archive_extension(path: &Path) -> bool {
+	match path.extension().and_then(|ext| ext.to_str()) {
+		Some(ext) => {
+			ARCHIVE_EXTENSIONS.iter().any(|archive_ext| ext.eq_ignore_ascii_case(archive_ext))
+		}
+		None => false,
+	}
+}
+
+/// Splits the path of a file inside an archive into the path of the archive and the name of
+/// the file within the archive. Returns `None` if the path doesn't point into an archive.
+///
+/// This is called for every file that is loaded, so only the ancestors with the extension of
+/// an archive are looked up on the disk. A path without such an ancestor is never looked up.
+pub fn split_archive_path(path: &Path) -> Option<(&Path, &Path)> {
+	let archive = path
+		.ancestors()
+		.skip(1)
+		.filter(|ancestor| has_archive_extension(ancestor))
+		.find(|ancestor| ancestor.is_file())?;
+	Some((archive, path.strip_prefix(archive).ok()?))
+}
+
+/// Same as `fs::canonicalize` but also accepts paths that point into an archive
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+	match split_archive_path(path) {
+		Some((archive, name)) => Ok(archive.canonicalize()?.join(name)),
+		None => path.canonicalize(),
+	}
+}
+
+/// Same as `fs::metadata` except that the files inside an archive get the metadata of the
+/// archive itself
+pub fn metadata(path: &Path) -> io::Result<fs::Metadata> {
+	match split_archive_path(path) {
+		Some((archive, _)) => fs::metadata(archive),
+		None => fs::metadata(path),
+	}
+}
+
+/// Same as `fs::read` but also reads the files inside archives
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+	let (archive_path, name) = match split_archive_path(path) {
+		Some(split) => split,
+		None => return fs::read(path),
+	};
+	let mut archive = open(archive_path)?;
+	let mut file = archive.by_name(&member_name(name)).map_err(to_io_error)?;
+	let mut bytes = Vec::with_capacity(file.size() as usize);
+	file.read_to_end(&mut bytes)?;
+	Ok(bytes)
+}
+
+/// Returns the names of the files in the archive, without the directories. The files whose
+/// names are absolute or lead out of the archive are left out, joined to the path of the
+/// archive they would point to a file outside of it.
+pub fn list_files(archive_path: &Path) -> io::Result<Vec<String>> {
+	let mut archive = open(archive_path)?;
+	let mut names = Vec::with_capacity(archive.len());
+	for index in 0..archive.len() {
+		let file = archive.by_index(index).map_err(to_io_error)?;
+		if !file.is_dir() && file.enclosed_name().is_some() {
+			names.push(file.name().to_owned());
+		}
+	}
+	Ok(names)
+}
+
+fn open(archive_path: &Path) -> io::Result<zip::ZipArchive<fs::File>> {
+	zip::ZipArchive::new(fs::File::open(archive_path)?).map_err(to_io_error)
+}
+
+/// The names within an archive always use forward slashes as the separator
+fn member_name(name: &Path) -> String {
+	let components: Vec<_> =
+		name.components().map(|component| component.as_os_str().to_string_lossy()).collect();
+	components.join("/")
+}
+
+fn to_io_error(error: zip::result::ZipError) -> io::Error {
+	match error {
+		zip::result::ZipError::Io(error) => error,
+		error => io::Error::new(io::ErrorKind::InvalidData, error),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Write;
+
+	fn write_archive(name: &str, members: &[&str]) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("emulsion-test-{}.cbz", name));
+		let mut writer = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+		for member in members {
+			let options = zip::write::FileOptions::default()
+				.compression_method(zip::CompressionMethod::Stored);
+			writer.start_file(*member, options).unwrap();
+			writer.write_all(member.as_bytes()).unwrap();
+		}
+		writer.finish().unwrap();
+		path
+	}
+
+	#[test]
+	fn members_outside_of_the_archive_are_not_listed() {
+		let path = write_archive("escape", &["page1.png", "../page2.png", "/page3.png", "a/b.png"]);
+		let names = list_files(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(names, vec!["page1.png", "a/b.png"]);
+	}
+
+	#[test]
+	fn members_are_read_from_the_archive() {
+		let path = write_archive("read", &["a/b.png"]);
+		let member = path.join("a").join("b.png");
+		assert_eq!(split_archive_path(&member), Some((path.as_path(), Path::new("a/b.png"))));
+		let bytes = read(&member).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(bytes, b"a/b.png");
+	}
+
+	#[test]
+	fn paths_without_an_archive_are_not_split() {
+		assert_eq!(split_archive_path(Path::new("/no/such/dir.zip/image.png")), None);
+		assert_eq!(split_archive_path(Path::new("/no/such/image.png")), None);
+	}
+}
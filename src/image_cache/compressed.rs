@@ -0,0 +1,232 @@
+//! Loading of the GPU compressed textures stored in DDS and KTX files. The compressed blocks are
+//! uploaded to the GPU as they are, the GPU decompresses them when the texture is sampled.
+//!
+//! Only the top level of the mipmap chain is loaded and only the S3TC (BC1, BC2, BC3) and BPTC
+//! (BC7) formats are supported. The colors are always treated as sRGB encoded, the same way as
+//! the colors of every other image.
+
+use std::convert::TryInto;
+
+use gelatin::glium::{
+	self,
+	texture::{CompressedMipmapsOption, CompressedSrgbFormat, CompressedSrgbTexture2d},
+};
+
+use super::image_loader::errors::*;
+
+const DDS_MAGIC: &[u8] = b"DDS ";
+const KTX_IDENTIFIER: &[u8] =
+	&[0xAB, 0x4B, 0x54, 0x58, 0x20, 0x31, 0x31, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// The part of the file that precedes the pixel data of a DDS
+const DDS_HEADER_SIZE: usize = 128;
+/// The size of the extended header that follows the regular one if the FourCC is `DX10`
+const DDS_DX10_HEADER_SIZE: usize = 20;
+const KTX_HEADER_SIZE: usize = 64;
+
+/// The top mipmap level of a compressed texture
+pub struct CompressedImage {
+	pub width: u32,
+	pub height: u32,
+	pub format: CompressedSrgbFormat,
+	/// The compressed blocks of the image
+	pub data: Vec<u8>,
+}
+
+impl CompressedImage {
+	/// Reads a DDS or KTX file. The two are told apart by their signature.
+	pub fn parse(bytes: &[u8]) -> Result<CompressedImage> {
+		if bytes.starts_with(DDS_MAGIC) {
+			parse_dds(bytes)
+		} else if bytes.starts_with(KTX_IDENTIFIER) {
+			parse_ktx(bytes)
+		} else {
+			bail!("The file is neither a DDS nor a KTX file")
+		}
+	}
+}
+
+/// Uploads the compressed blocks as they are, the texture is decompressed when it's sampled
+pub fn upload(
+	display: &glium::Display,
+	image: &CompressedImage,
+) -> Result<CompressedSrgbTexture2d> {
+	Ok(CompressedSrgbTexture2d::with_compressed_data(
+		display,
+		image.data.as_slice(),
+		image.width,
+		image.height,
+		image.format,
+		CompressedMipmapsOption::NoMipmap,
+	)?)
+}
+
+fn parse_dds(bytes: &[u8]) -> Result<CompressedImage> {
+	if bytes.len() < DDS_HEADER_SIZE {
+		bail!("The DDS header is truncated");
+	}
+	let height = read_u32(bytes, 12, false)?;
+	let width = read_u32(bytes, 16, false)?;
+	let (format, data_offset) = match &bytes[84..88] {
+		b"DXT1" => (CompressedSrgbFormat::S3tcDxt1Alpha, DDS_HEADER_SIZE),
+		// DXT2 and DXT4 are the premultiplied alpha versions of DXT3 and DXT5
+		b"DXT2" | b"DXT3" => (CompressedSrgbFormat::S3tcDxt3Alpha, DDS_HEADER_SIZE),
+		b"DXT4" | b"DXT5" => (CompressedSrgbFormat::S3tcDxt5Alpha, DDS_HEADER_SIZE),
+		b"DX10" => {
+			let format = match read_u32(bytes, DDS_HEADER_SIZE, false)? {
+				// The DXGI_FORMAT values of the unorm and the srgb versions
+				71 | 72 => CompressedSrgbFormat::S3tcDxt1Alpha,
+				74 | 75 => CompressedSrgbFormat::S3tcDxt3Alpha,
+				77 | 78 => CompressedSrgbFormat::S3tcDxt5Alpha,
+				98 | 99 => CompressedSrgbFormat::Bptc,
+				other => bail!(format!("Unsupported DXGI format {} in DDS", other)),
+			};
+			(format, DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE)
+		}
+		other => bail!(format!("Unsupported FourCC '{}' in DDS", String::from_utf8_lossy(other))),
+	};
+	let data = read_level(bytes, data_offset, format, width, height)?;
+	Ok(CompressedImage { width, height, format, data })
+}
+
+fn parse_ktx(bytes: &[u8]) -> Result<CompressedImage> {
+	if bytes.len() < KTX_HEADER_SIZE {
+		bail!("The KTX header is truncated");
+	}
+	// The writer stores 0x04030201 in its own endianness
+	let big_endian = match read_u32(bytes, 12, false)? {
+		0x0403_0201 => false,
+		0x0102_0304 => true,
+		_ => bail!("Invalid endianness in KTX"),
+	};
+	let format = match read_u32(bytes, 28, big_endian)? {
+		// The glInternalFormat values of the linear and the sRGB versions
+		0x83F0 | 0x8C4C => CompressedSrgbFormat::S3tcDxt1NoAlpha,
+		0x83F1 | 0x8C4D => CompressedSrgbFormat::S3tcDxt1Alpha,
+		0x83F2 | 0x8C4E => CompressedSrgbFormat::S3tcDxt3Alpha,
+		0x83F3 | 0x8C4F => CompressedSrgbFormat::S3tcDxt5Alpha,
+		0x8E8C | 0x8E8D => CompressedSrgbFormat::Bptc,
+		other => bail!(format!("Unsupported internal format {:#x} in KTX", other)),
+	};
+	let width = read_u32(bytes, 36, big_endian)?;
+	// One dimensional textures have a height of 0
+	let height = read_u32(bytes, 40, big_endian)?.max(1);
+	let key_value_size = read_u32(bytes, 60, big_endian)? as usize;
+	// The data of every mipmap level is preceded by its size
+	let data_offset = KTX_HEADER_SIZE + key_value_size + 4;
+	let data = read_level(bytes, data_offset, format, width, height)?;
+	Ok(CompressedImage { width, height, format, data })
+}
+
+/// The number of bytes that the blocks of a `width` by `height` image take up
+pub fn block_data_size(format: CompressedSrgbFormat, width: u32, height: u32) -> usize {
+	let block_size = match format {
+		CompressedSrgbFormat::S3tcDxt1NoAlpha | CompressedSrgbFormat::S3tcDxt1Alpha => 8,
+		_ => 16,
+	};
+	// Every block encodes 4 by 4 texels
+	(width as usize).div_ceil(4) * (height as usize).div_ceil(4) * block_size
+}
+
+/// Returns the compressed blocks of the `width` by `height` level that starts at `offset`
+fn read_level(
+	bytes: &[u8],
+	offset: usize,
+	format: CompressedSrgbFormat,
+	width: u32,
+	height: u32,
+) -> Result<Vec<u8>> {
+	if width == 0 || height == 0 {
+		bail!("The texture is empty");
+	}
+	match bytes.get(offset..offset + block_data_size(format, width, height)) {
+		Some(data) => Ok(data.to_vec()),
+		None => bail!("The texture data is truncated"),
+	}
+}
+
+fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u32> {
+	let word = match bytes.get(offset..offset + 4) {
+		Some(word) => word.try_into().unwrap(),
+		None => bail!("The texture header is truncated"),
+	};
+	Ok(if big_endian { u32::from_be_bytes(word) } else { u32::from_le_bytes(word) })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn dds(four_cc: &[u8], width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+		let mut bytes = vec![0; DDS_HEADER_SIZE];
+		bytes[..4].copy_from_slice(DDS_MAGIC);
+		bytes[12..16].copy_from_slice(&height.to_le_bytes());
+		bytes[16..20].copy_from_slice(&width.to_le_bytes());
+		bytes[84..88].copy_from_slice(four_cc);
+		bytes.extend_from_slice(data);
+		bytes
+	}
+
+	fn ktx(internal_format: u32, width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+		let key_values = [0u8; 8];
+		let mut bytes = vec![0; KTX_HEADER_SIZE];
+		bytes[..12].copy_from_slice(KTX_IDENTIFIER);
+		bytes[12..16].copy_from_slice(&0x0403_0201u32.to_le_bytes());
+		bytes[28..32].copy_from_slice(&internal_format.to_le_bytes());
+		bytes[36..40].copy_from_slice(&width.to_le_bytes());
+		bytes[40..44].copy_from_slice(&height.to_le_bytes());
+		bytes[60..64].copy_from_slice(&(key_values.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(&key_values);
+		bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(data);
+		bytes
+	}
+
+	#[test]
+	fn partial_blocks_take_up_whole_blocks() {
+		assert_eq!(block_data_size(CompressedSrgbFormat::S3tcDxt1Alpha, 4, 4), 8);
+		assert_eq!(block_data_size(CompressedSrgbFormat::S3tcDxt1Alpha, 5, 5), 32);
+		assert_eq!(block_data_size(CompressedSrgbFormat::Bptc, 1, 1), 16);
+		assert_eq!(block_data_size(CompressedSrgbFormat::S3tcDxt5Alpha, 8, 4), 32);
+	}
+
+	#[test]
+	fn dds_files_are_parsed() {
+		let data: Vec<u8> = (0..16).collect();
+		let image = CompressedImage::parse(&dds(b"DXT1", 8, 4, &data)).unwrap();
+		assert_eq!((image.width, image.height), (8, 4));
+		assert!(matches!(image.format, CompressedSrgbFormat::S3tcDxt1Alpha));
+		assert_eq!(image.data, data);
+	}
+
+	#[test]
+	fn dds_files_with_the_extended_header_are_parsed() {
+		let mut extended = vec![0; DDS_DX10_HEADER_SIZE];
+		extended[..4].copy_from_slice(&98u32.to_le_bytes());
+		extended.extend_from_slice(&[7; 16]);
+		let image = CompressedImage::parse(&dds(b"DX10", 4, 4, &extended)).unwrap();
+		assert!(matches!(image.format, CompressedSrgbFormat::Bptc));
+		assert_eq!(image.data, vec![7; 16]);
+	}
+
+	#[test]
+	fn truncated_dds_files_are_rejected() {
+		assert!(CompressedImage::parse(&dds(b"DXT5", 4, 4, &[0; 15])).is_err());
+		assert!(CompressedImage::parse(&dds(b"ATI2", 4, 4, &[0; 16])).is_err());
+	}
+
+	#[test]
+	fn ktx_files_are_parsed() {
+		let data: Vec<u8> = (0..32).collect();
+		let image = CompressedImage::parse(&ktx(0x8C4F, 4, 8, &data)).unwrap();
+		assert_eq!((image.width, image.height), (4, 8));
+		assert!(matches!(image.format, CompressedSrgbFormat::S3tcDxt5Alpha));
+		assert_eq!(image.data, data);
+	}
+
+	#[test]
+	fn one_dimensional_ktx_files_are_one_texel_high() {
+		let image = CompressedImage::parse(&ktx(0x83F0, 8, 0, &[0; 16])).unwrap();
+		assert_eq!((image.width, image.height), (8, 1));
+	}
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+
+use notify::{op, RawEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Keeps track of files being added to or removed from a directory.
+pub struct DirWatcher {
+	// The watching stops when this is dropped
+	_watcher: RecommendedWatcher,
+	event_rx: Receiver<RawEvent>,
+}
+
+impl DirWatcher {
+	pub fn new(dir_path: &Path, recursive: bool) -> notify::Result<DirWatcher> {
+		let (event_tx, event_rx) = channel();
+		let mut watcher = notify::raw_watcher(event_tx)?;
+		let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+		watcher.watch(dir_path, mode)?;
+		Ok(DirWatcher { _watcher: watcher, event_rx })
+	}
+
+	/// Returns true if files were created, removed, or renamed in the directory since the last
+	/// time this function was called.
+	pub fn listing_changed(&self) -> bool {
+		let mut changed = false;
+		while let Ok(event) = self.event_rx.try_recv() {
+			if let Ok(event_op) = event.op {
+				if event_op.intersects(op::CREATE | op::REMOVE | op::RENAME | op::RESCAN) {
+					changed = true;
+				}
+			}
+		}
+		changed
+	}
+}
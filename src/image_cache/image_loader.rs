@@ -1,16 +1,28 @@
-use std;
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
 use std::fs;
+use std::io::{self, Cursor};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
 use std::thread;
+use std::time::{Duration, Instant};
 
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
 use gelatin::glium;
 use gelatin::image;
 
-use glium::texture::{RawImage2d, SrgbTexture2d};
+use glium::texture::{ClientFormat, RawImage2d, SrgbTexture2d};
+#[cfg(feature = "compressed")]
+use glium::texture::{CompressedSrgbFormat, CompressedSrgbTexture2d};
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
 
 pub mod errors {
 	use gelatin::glium::texture;
@@ -27,91 +39,1956 @@ pub mod errors {
 }
 
 use self::errors::*;
+use super::archive;
+#[cfg(feature = "compressed")]
+use super::compressed::{self, CompressedImage};
 
-pub fn load_image(image_path: &Path) -> Result<image::RgbaImage> {
-	Ok(image::open(image_path)?.to_rgba())
+/// Options that affect how the images are decoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadSettings {
+	/// Images that are wider or taller than this are scaled down to fit, keeping their
+	/// aspect ratio
+	pub max_dimension: Option<u32>,
+	/// Images that are wider or taller than this are decoded at a reduced resolution, which
+	/// avoids allocating the full resolution pixels. Only JPEGs can be decoded scaled, this
+	/// doesn't affect the other formats. The decoded JPEG may be larger than this, see
+	/// `scaled_jpeg_dimensions`.
+	pub max_decode_dimension: Option<u32>,
+	/// Convert the colors of JPEGs that have an embedded ICC profile to sRGB
+	pub color_management: bool,
+	/// The resolution that SVG images are rasterized at. This determines the size of
+	/// lengths given in physical units like `mm` or `in`.
+	pub svg_dpi: f32,
+	/// Whether the colors of the images are sRGB encoded. When false, the values are treated
+	/// as linear, like the ones of normal maps, and they are displayed without gamma decoding.
+	pub srgb: bool,
+	/// How reading the file is retried when it fails
+	pub retry: RetryPolicy,
+	pub adjustment: ColorAdjustment,
+	/// Multiply the colors by their alpha, for renderers that blend with premultiplied alpha
+	pub premultiply_alpha: bool,
+	/// Hash the contents of the loaded files with `content_hash`, to recognize the edits that
+	/// keep the modification time
+	pub content_hashing: bool,
 }
 
+impl Default for LoadSettings {
+	fn default() -> LoadSettings {
+		LoadSettings {
+			max_dimension: None,
+			max_decode_dimension: None,
+			color_management: false,
+			svg_dpi: 96.0,
+			srgb: true,
+			retry: RetryPolicy::default(),
+			adjustment: ColorAdjustment::default(),
+			premultiply_alpha: false,
+			content_hashing: false,
+		}
+	}
+}
+
+/// A change of the brightness that is baked into the textures, for example to see the
+/// details of underexposed photos
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorAdjustment {
+	/// Values above 1 brighten the dark tones, values below 1 darken them
+	pub gamma: f32,
+	/// The change of the exposure in stops, every stop doubles the amount of light
+	pub exposure: f32,
+}
+
+impl ColorAdjustment {
+	pub fn is_identity(&self) -> bool {
+		self.gamma == 1.0 && self.exposure == 0.0
+	}
+}
+
+impl Default for ColorAdjustment {
+	fn default() -> ColorAdjustment {
+		ColorAdjustment { gamma: 1.0, exposure: 0.0 }
+	}
+}
+
+/// Files on network shares sometimes can't be read for a moment, for example while the disk
+/// is spinning up. Operations that fail with an IO error are retried `retries` times, waiting
+/// `initial_delay` before the first retry and twice as long before every next one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+	pub retries: u32,
+	pub initial_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> RetryPolicy {
+		RetryPolicy { retries: 0, initial_delay: Duration::from_millis(100) }
+	}
+}
+
+/// Runs `operation` until it succeeds or fails with an error other than an IO error, or until
+/// the retries run out. Decoding errors are returned right away because reading the file
+/// again won't fix them.
+pub fn retry_io<T>(policy: RetryPolicy, mut operation: impl FnMut() -> Result<T>) -> Result<T> {
+	let mut delay = policy.initial_delay;
+	let mut retries = 0;
+	loop {
+		match operation() {
+			Err(Error(ErrorKind::Io(_), _)) if retries < policy.retries => {
+				thread::sleep(delay);
+				delay *= 2;
+				retries += 1;
+			}
+			result => return result,
+		}
+	}
+}
+
+/// What the file was decoded from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ImageInfo {
+	pub format: image::ImageFormat,
+	/// The color type stored in the file, before it was converted to RGBA
+	pub color_type: image::ColorType,
+}
+
+impl ImageInfo {
+	pub fn bits_per_pixel(&self) -> u16 {
+		self.color_type.bits_per_pixel()
+	}
+}
+
+/// The contents of a decoded file
+pub enum DecodedImage {
+	/// Indexed PNGs and GIFs are expanded to RGBA as well, because the picture widget and the
+	/// thumbnails only sample RGBA textures
+	Still(image::RgbaImage),
+	/// The frames of an animated GIF or PNG, each with the duration it should be displayed for
+	Animation(Vec<(image::RgbaImage, Duration)>),
+	/// The pages of a multi-page TIFF
+	Pages(Vec<image::RgbaImage>),
+	/// The blocks of a GPU compressed texture from a DDS or KTX file
+	#[cfg(feature = "compressed")]
+	Compressed(CompressedImage),
+}
+
+impl DecodedImage {
+	/// The still image, or the first frame of an animation or the first page of a document.
+	/// Compressed textures have no pixels to return.
+	pub fn into_first_frame(self) -> Result<image::RgbaImage> {
+		match self {
+			DecodedImage::Still(image) => Ok(image),
+			DecodedImage::Animation(frames) => {
+				Ok(frames.into_iter().next().ok_or("The animation has no frames")?.0)
+			}
+			DecodedImage::Pages(pages) => {
+				Ok(pages.into_iter().next().ok_or("The document has no pages")?)
+			}
+			#[cfg(feature = "compressed")]
+			DecodedImage::Compressed(_) => bail!("Compressed textures can't be decoded to pixels"),
+		}
+	}
+
+	pub fn size_estimate(&self) -> isize {
+		match self {
+			DecodedImage::Still(image) => get_image_size_estimate(image.dimensions()) as isize,
+			DecodedImage::Animation(frames) => frames
+				.iter()
+				.map(|(image, _)| get_image_size_estimate(image.dimensions()) as isize)
+				.sum(),
+			DecodedImage::Pages(pages) => {
+				pages.iter().map(|image| get_image_size_estimate(image.dimensions()) as isize).sum()
+			}
+			// The blocks are uploaded as they are
+			#[cfg(feature = "compressed")]
+			DecodedImage::Compressed(image) => image.data.len() as isize,
+		}
+	}
+
+	/// Mirrors every frame horizontally. Compressed textures can't be flipped.
+	pub fn flipped_horizontally(self) -> DecodedImage {
+		let flip = |image: image::RgbaImage| image::imageops::flip_horizontal(&image);
+		match self {
+			DecodedImage::Still(image) => DecodedImage::Still(flip(image)),
+			DecodedImage::Animation(frames) => DecodedImage::Animation(
+				frames.into_iter().map(|(image, delay)| (flip(image), delay)).collect(),
+			),
+			DecodedImage::Pages(pages) => {
+				DecodedImage::Pages(pages.into_iter().map(flip).collect())
+			}
+			#[cfg(feature = "compressed")]
+			DecodedImage::Compressed(image) => DecodedImage::Compressed(image),
+		}
+	}
+
+	/// Rotates every frame clockwise by `degrees`, which must be 90, 180 or 270. Other values
+	/// leave the image unchanged. Compressed textures can't be rotated.
+	pub fn rotated(self, degrees: u32) -> DecodedImage {
+		let rotate = |image: image::RgbaImage| match degrees {
+			90 => image::imageops::rotate90(&image),
+			180 => image::imageops::rotate180(&image),
+			270 => image::imageops::rotate270(&image),
+			_ => image,
+		};
+		match self {
+			DecodedImage::Still(image) => DecodedImage::Still(rotate(image)),
+			DecodedImage::Animation(frames) => DecodedImage::Animation(
+				frames.into_iter().map(|(image, delay)| (rotate(image), delay)).collect(),
+			),
+			DecodedImage::Pages(pages) => {
+				DecodedImage::Pages(pages.into_iter().map(rotate).collect())
+			}
+			#[cfg(feature = "compressed")]
+			DecodedImage::Compressed(image) => DecodedImage::Compressed(image),
+		}
+	}
+}
+
+fn read_image_file(image_path: &Path) -> Result<(Vec<u8>, image::ImageFormat)> {
+	// Read the file only once, both the EXIF metadata and the pixels are decoded from these bytes
+	let bytes = archive::read(image_path)?;
+	let format = match image::ImageFormat::from_path(image_path) {
+		Ok(format) => format,
+		// The extension may be one that was added by the user, try to figure out the format
+		// from the contents instead
+		Err(_) => image::guess_format(bytes.as_slice())?,
+	};
+	Ok((bytes, format))
+}
+
+/// Hashes the size of the file and its first and last few kilobytes. This catches most of the
+/// edits that keep the modification time, without reading the whole file. The files inside
+/// archives are hashed in full because they can't be read partially.
+pub fn content_hash(path: &Path) -> io::Result<u64> {
+	use std::collections::hash_map::DefaultHasher;
+	use std::hash::Hasher;
+	use std::io::{Read, Seek, SeekFrom};
+
+	const SAMPLE_SIZE: u64 = 64 * 1024;
+
+	let mut hasher = DefaultHasher::new();
+	if archive::split_archive_path(path).is_some() {
+		hasher.write(archive::read(path)?.as_slice());
+		return Ok(hasher.finish());
+	}
+	let mut file = fs::File::open(path)?;
+	let len = file.metadata()?.len();
+	hasher.write_u64(len);
+	let mut sample = Vec::with_capacity(2 * SAMPLE_SIZE as usize);
+	(&mut file).take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+	if len > SAMPLE_SIZE {
+		// The two samples don't overlap
+		file.seek(SeekFrom::Start((len - SAMPLE_SIZE).max(SAMPLE_SIZE)))?;
+		file.take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+	}
+	hasher.write(sample.as_slice());
+	Ok(hasher.finish())
+}
+
+/// The hash of the file if the settings ask for content hashing and the file can be read
+pub fn loaded_content_hash(image_path: &Path, settings: &LoadSettings) -> Option<u64> {
+	if settings.content_hashing {
+		content_hash(image_path).ok()
+	} else {
+		None
+	}
+}
+
+/// Reads the width and height of the image from the header of the file without decoding the
+/// pixels. The EXIF orientation and the load settings are not taken into account.
+pub fn read_image_dimensions(image_path: &Path) -> Result<(u32, u32)> {
+	if archive::split_archive_path(image_path).is_none() {
+		return Ok(image::image_dimensions(image_path)?);
+	}
+	let (bytes, format) = read_image_file(image_path)?;
+	Ok(image::io::Reader::with_format(Cursor::new(bytes), format).into_dimensions()?)
+}
+
+/// Decodes the images for the cache, including the thumbnails and the images loaded from
+/// memory. Replacing the default `FileDecoder` with a stub that returns canned images lets
+/// the caching and prefetching be exercised without real image files. Together with an
+/// `Uploader` that doesn't create textures, no display is needed either.
+pub trait Decoder: Send + Sync {
+	fn decode(
+		&self,
+		image_path: &Path,
+		settings: &LoadSettings,
+	) -> Result<(DecodedImage, Option<ImageInfo>)>;
+
+	/// The width and height of the image, without decoding it if possible
+	fn dimensions(&self, image_path: &Path) -> Result<(u32, u32)>;
+
+	/// The first frame of the image scaled down to fit in a `max_size` by `max_size` square
+	fn thumbnail(
+		&self,
+		image_path: &Path,
+		max_size: u32,
+		settings: &LoadSettings,
+	) -> Result<image::RgbaImage> {
+		let settings = LoadSettings { max_dimension: Some(max_size), ..settings.clone() };
+		self.decode(image_path, &settings)?.0.into_first_frame()
+	}
+
+	/// The largest square out of the center of the first frame, scaled to `size` by `size`
+	fn square_thumbnail(
+		&self,
+		image_path: &Path,
+		size: u32,
+		settings: &LoadSettings,
+	) -> Result<image::RgbaImage> {
+		let settings = LoadSettings { max_dimension: None, ..settings.clone() };
+		Ok(crop_to_square(self.decode(image_path, &settings)?.0.into_first_frame()?, size))
+	}
+
+	/// Decodes an image that is already in memory, its format is guessed from the contents
+	fn decode_bytes(&self, bytes: &[u8], settings: &LoadSettings) -> Result<image::RgbaImage> {
+		load_image_from_memory(bytes, settings)
+	}
+}
+
+/// Decodes the files with `load_image`
+pub struct FileDecoder;
+
+impl Decoder for FileDecoder {
+	fn decode(
+		&self,
+		image_path: &Path,
+		settings: &LoadSettings,
+	) -> Result<(DecodedImage, Option<ImageInfo>)> {
+		load_image(image_path, settings)
+	}
+
+	fn dimensions(&self, image_path: &Path) -> Result<(u32, u32)> {
+		read_image_dimensions(image_path)
+	}
+
+	fn thumbnail(
+		&self,
+		image_path: &Path,
+		max_size: u32,
+		settings: &LoadSettings,
+	) -> Result<image::RgbaImage> {
+		load_thumbnail_image(image_path, max_size, settings)
+	}
+
+	fn square_thumbnail(
+		&self,
+		image_path: &Path,
+		size: u32,
+		settings: &LoadSettings,
+	) -> Result<image::RgbaImage> {
+		load_square_thumbnail_image(image_path, size, settings)
+	}
+}
+
+/// Loads the image from the file. GIFs and PNGs with more than one frame are returned as
+/// animations.
+///
+/// The info is only returned for the formats that are decoded by the `image` crate.
+pub fn load_image(
+	image_path: &Path,
+	settings: &LoadSettings,
+) -> Result<(DecodedImage, Option<ImageInfo>)> {
+	use image::{AnimationDecoder, ImageDecoder};
+
+	#[cfg(feature = "compressed")]
+	{
+		if has_extension(image_path, COMPRESSED_EXTENSIONS) {
+			let image = CompressedImage::parse(archive::read(image_path)?.as_slice())?;
+			return Ok((DecodedImage::Compressed(image), None));
+		}
+	}
+	if let Some(image) = load_external_format(image_path, settings)? {
+		return Ok((DecodedImage::Still(image), None));
+	}
+
+	let (bytes, format) = read_image_file(image_path)?;
+	let (frames, color_type) = match format {
+		image::ImageFormat::Gif => {
+			let decoder = GifDecoder::new(Cursor::new(bytes.as_slice()))?;
+			let color_type = decoder.color_type();
+			// Only the animations need the rest of the frames, a still GIF is made of the
+			// first one instead of decoding the file again
+			let mut frames = decoder.into_frames();
+			match (frames.next().transpose()?, frames.next().transpose()?) {
+				(Some(first), Some(second)) => {
+					let mut all_frames = vec![first, second];
+					all_frames.extend(frames.collect_frames()?);
+					(all_frames, color_type)
+				}
+				(Some(first), None) => {
+					let image = apply_settings(first.into_buffer(), settings);
+					let info = ImageInfo { format, color_type };
+					return Ok((DecodedImage::Still(image), Some(info)));
+				}
+				(None, _) => (Vec::new(), color_type),
+			}
+		}
+		image::ImageFormat::Png => {
+			// Only the animated PNGs have frames. Animations that the decoder can't composite
+			// (for example ones with 16 bit colors) are displayed as still images.
+			let decoder = PngDecoder::new(Cursor::new(bytes.as_slice()))?;
+			let color_type = decoder.color_type();
+			if decoder.is_apng() {
+				(decoder.apng().into_frames().collect_frames().unwrap_or_default(), color_type)
+			} else {
+				(Vec::new(), color_type)
+			}
+		}
+		image::ImageFormat::WebP => decode_webp_frames(bytes.as_slice())?,
+		_ => (Vec::new(), image::ColorType::Rgba8),
+	};
+	if frames.len() > 1 {
+		let frames = frames
+			.into_iter()
+			.map(|frame| {
+				let delay = Duration::from(frame.delay());
+				(apply_settings(frame.into_buffer(), settings), delay)
+			})
+			.collect();
+		let info = ImageInfo { format, color_type };
+		return Ok((DecodedImage::Animation(frames), Some(info)));
+	}
+	// PDF documents aren't supported, they would need a renderer rather than a decoder
+	if format == image::ImageFormat::Tiff {
+		// Documents that can't be decoded page by page are displayed as still images
+		if let Ok((pages, color_type)) = decode_tiff_pages(bytes.as_slice(), settings) {
+			if pages.len() > 1 {
+				let info = ImageInfo { format, color_type };
+				return Ok((DecodedImage::Pages(pages), Some(info)));
+			}
+		}
+	}
+	let (image, color_type) = decode_image_with_color_type(bytes.as_slice(), format, settings)?;
+	let info = Some(ImageInfo { format, color_type });
+	Ok((DecodedImage::Still(image), info))
+}
+
+/// Decodes every frame of an animated WebP, which the `image` crate can't do. There are no
+/// frames if the file is a still image.
+fn decode_webp_frames(bytes: &[u8]) -> Result<(Vec<image::Frame>, image::ColorType)> {
+	use image_webp::WebPDecoder;
+
+	let webp_error =
+		|err: image_webp::DecodingError| Error::from(format!("Failed to decode WebP: {}", err));
+	let mut decoder = WebPDecoder::new(Cursor::new(bytes)).map_err(webp_error)?;
+	let color_type =
+		if decoder.has_alpha() { image::ColorType::Rgba8 } else { image::ColorType::Rgb8 };
+	if !decoder.is_animated() {
+		return Ok((Vec::new(), color_type));
+	}
+	let (width, height) = decoder.dimensions();
+	let buffer_size = decoder.output_buffer_size().ok_or("The WebP animation is too large")?;
+	let mut frames = Vec::with_capacity(decoder.num_frames() as usize);
+	for _ in 0..decoder.num_frames() {
+		let mut buffer = vec![0; buffer_size];
+		let delay = decoder.read_frame(buffer.as_mut_slice()).map_err(webp_error)?;
+		// The frames are composited onto the canvas by the decoder
+		let image = if decoder.has_alpha() {
+			image::RgbaImage::from_raw(width, height, buffer).map(image::DynamicImage::ImageRgba8)
+		} else {
+			image::RgbImage::from_raw(width, height, buffer).map(image::DynamicImage::ImageRgb8)
+		};
+		let image = image.ok_or("The WebP frame doesn't match the size of the animation")?;
+		let delay = image::Delay::from_numer_denom_ms(delay, 1);
+		frames.push(image::Frame::from_parts(image.into_rgba8(), 0, 0, delay));
+	}
+	Ok((frames, color_type))
+}
+
+/// Decodes every page of a TIFF, which the `image` crate can't do. Only the grayscale and the
+/// RGB pages with 8 or 16 bits per sample are supported. The orientation and the ICC profile
+/// of each page are applied like for the other images. Returns the color type of the first
+/// page.
+fn decode_tiff_pages(
+	bytes: &[u8],
+	settings: &LoadSettings,
+) -> Result<(Vec<image::RgbaImage>, image::ColorType)> {
+	use tiff::decoder::{Decoder, DecodingResult};
+	use tiff::tags::Tag;
+	use tiff::ColorType;
+
+	const ICC_PROFILE_TAG: u16 = 34675;
+
+	let tiff_error = |err: tiff::TiffError| Error::from(format!("Failed to decode TIFF: {}", err));
+	let mut decoder = Decoder::new(Cursor::new(bytes)).map_err(tiff_error)?;
+	if !decoder.more_images() {
+		// Decoding a single page is left to the `image` crate
+		return Ok((Vec::new(), image::ColorType::Rgba8));
+	}
+	let mut pages = Vec::new();
+	let mut first_color_type = None;
+	loop {
+		let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+		let (channels, color_type) = match decoder.colortype().map_err(tiff_error)? {
+			ColorType::Gray(8) => (1, image::ColorType::L8),
+			ColorType::Gray(16) => (1, image::ColorType::L16),
+			ColorType::GrayA(8) => (2, image::ColorType::La8),
+			ColorType::GrayA(16) => (2, image::ColorType::La16),
+			ColorType::RGB(8) => (3, image::ColorType::Rgb8),
+			ColorType::RGB(16) => (3, image::ColorType::Rgb16),
+			ColorType::RGBA(8) => (4, image::ColorType::Rgba8),
+			ColorType::RGBA(16) => (4, image::ColorType::Rgba16),
+			other => bail!(format!("Unsupported TIFF color type {:?}", other)),
+		};
+		first_color_type.get_or_insert(color_type);
+		let orientation = decoder.find_tag_unsigned(Tag::Orientation).ok().flatten().unwrap_or(1);
+		let icc_profile = decoder.get_tag_u8_vec(Tag::Unknown(ICC_PROFILE_TAG)).ok();
+		// Only the most significant byte of 16 bit samples is kept
+		let samples: Vec<u8> = match decoder.read_image().map_err(tiff_error)? {
+			DecodingResult::U8(samples) => samples,
+			DecodingResult::U16(samples) => {
+				samples.iter().map(|sample| (sample >> 8) as u8).collect()
+			}
+			_ => bail!("Unsupported TIFF sample format"),
+		};
+		let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+		for pixel in samples.chunks_exact(channels) {
+			match *pixel {
+				[luma] => rgba.extend_from_slice(&[luma, luma, luma, 255]),
+				[luma, alpha] => rgba.extend_from_slice(&[luma, luma, luma, alpha]),
+				[r, g, b] => rgba.extend_from_slice(&[r, g, b, 255]),
+				_ => rgba.extend_from_slice(pixel),
+			}
+		}
+		let page = image::RgbaImage::from_raw(width, height, rgba)
+			.ok_or("The TIFF page has fewer pixels than its size")?;
+		pages.push(correct_image(page, icc_profile.as_deref(), orientation, settings));
+		if !decoder.more_images() {
+			break;
+		}
+		decoder.next_image().map_err(tiff_error)?;
+	}
+	Ok((pages, first_color_type.unwrap_or(image::ColorType::Rgba8)))
+}
+
+/// Loads the first frame of the image scaled down to fit in a `max_size` by `max_size` square.
+pub fn load_thumbnail_image(
+	image_path: &Path,
+	max_size: u32,
+	settings: &LoadSettings,
+) -> Result<image::RgbaImage> {
+	let settings = LoadSettings { max_dimension: Some(max_size), ..settings.clone() };
+
+	if let Some(image) = load_external_format(image_path, &settings)? {
+		return Ok(image);
+	}
+
+	let (bytes, format) = read_image_file(image_path)?;
+	decode_image(bytes.as_slice(), format, &settings)
+}
+
+/// Loads the first frame of the image, crops the largest square out of its center and scales
+/// that to `size` by `size`.
+pub fn load_square_thumbnail_image(
+	image_path: &Path,
+	size: u32,
+	settings: &LoadSettings,
+) -> Result<image::RgbaImage> {
+	// The shorter side has to fit after cropping, so the image is decoded in full size
+	let settings = LoadSettings { max_dimension: None, ..settings.clone() };
+
+	let image = match load_external_format(image_path, &settings)? {
+		Some(image) => image,
+		None => {
+			let (bytes, format) = read_image_file(image_path)?;
+			decode_image(bytes.as_slice(), format, &settings)?
+		}
+	};
+	Ok(crop_to_square(image, size))
+}
+
+/// Crops the largest square out of the center of the image and scales it to `size` by `size`
+pub fn crop_to_square(mut image: image::RgbaImage, size: u32) -> image::RgbaImage {
+	let (width, height) = image.dimensions();
+	let side = width.min(height);
+	let cropped =
+		image::imageops::crop(&mut image, (width - side) / 2, (height - side) / 2, side, side)
+			.to_image();
+	if side == size {
+		return cropped;
+	}
+	image::imageops::resize(&cropped, size, size, image::imageops::FilterType::Triangle)
+}
+
+/// Decodes the formats that the `image` crate doesn't support. Returns `None` if the file is
+/// not in one of these formats or the feature of its format is disabled.
+#[cfg_attr(not(any(feature = "heic", feature = "svg", feature = "raw")), allow(unused_variables))]
+fn load_external_format(
+	image_path: &Path,
+	settings: &LoadSettings,
+) -> Result<Option<image::RgbaImage>> {
+	#[cfg(feature = "heic")]
+	{
+		if has_extension(image_path, HEIF_EXTENSIONS) {
+			let image = decode_heif(archive::read(image_path)?.as_slice())?;
+			return Ok(Some(apply_settings(image, settings)));
+		}
+	}
+	#[cfg(feature = "svg")]
+	{
+		if has_extension(image_path, SVG_EXTENSIONS) {
+			let image = rasterize_svg(archive::read(image_path)?.as_slice(), settings)?;
+			return Ok(Some(apply_settings(image, settings)));
+		}
+	}
+	#[cfg(feature = "raw")]
+	{
+		if has_extension(image_path, RAW_EXTENSIONS) {
+			let image = decode_raw(archive::read(image_path)?.as_slice(), settings)?;
+			return Ok(Some(apply_settings(image, settings)));
+		}
+	}
+	Ok(None)
+}
+
+pub fn has_extension(image_path: &Path, extensions: &[&str]) -> bool {
+	match image_path.extension().and_then(|ext| ext.to_str()) {
+		Some(ext) => extensions.iter().any(|extension| ext.eq_ignore_ascii_case(extension)),
+		None => false,
+	}
+}
+
+/// Renders the SVG image. The image is rendered at the size that fits within the maximal
+/// dimension instead of being scaled down after rendering.
+#[cfg(feature = "svg")]
+fn rasterize_svg(bytes: &[u8], settings: &LoadSettings) -> Result<image::RgbaImage> {
+	use resvg::{tiny_skia, usvg};
+
+	let options = usvg::Options { dpi: settings.svg_dpi, ..Default::default() };
+	let tree = usvg::Tree::from_data(bytes, &options).map_err(|err| err.to_string())?;
+	let size = tree.size();
+	let scale = match settings.max_dimension {
+		Some(max_dimension) => (max_dimension as f32 / size.width().max(size.height())).min(1.0),
+		None => 1.0,
+	};
+	let width = ((size.width() * scale).round() as u32).max(1);
+	let height = ((size.height() * scale).round() as u32).max(1);
+	let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("The SVG image is too large")?;
+	resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+	// The pixmap stores premultiplied colors
+	let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+	for pixel in pixmap.pixels() {
+		let color = pixel.demultiply();
+		pixels.extend_from_slice(&[color.red(), color.green(), color.blue(), color.alpha()]);
+	}
+	image::RgbaImage::from_raw(width, height, pixels)
+		.ok_or_else(|| "The rendered SVG image is incomplete".into())
+}
+
+/// Decodes the primary image of a HEIF file. The rotation and mirroring stored in the file
+/// are applied by libheif.
+#[cfg(feature = "heic")]
+fn decode_heif(bytes: &[u8]) -> Result<image::RgbaImage> {
+	use libheif_rs::{ColorSpace, HeifContext, RgbChroma};
+
+	let context = HeifContext::read_from_bytes(bytes).map_err(|err| err.to_string())?;
+	let handle = context.primary_image_handle().map_err(|err| err.to_string())?;
+	let image =
+		handle.decode(ColorSpace::Rgb(RgbChroma::Rgba), false).map_err(|err| err.to_string())?;
+	let plane = image.planes().interleaved.ok_or("The decoded HEIF image has no pixel data")?;
+
+	// The rows of the plane may be padded
+	let row_len = plane.width as usize * 4;
+	let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+	for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+		pixels.extend_from_slice(&row[..row_len]);
+	}
+	image::RgbaImage::from_raw(plane.width, plane.height, pixels)
+		.ok_or_else(|| "The decoded HEIF image is incomplete".into())
+}
+
+/// Demosaics the RAW photo. The image is developed at the size that fits within the maximal
+/// dimension because that's a lot faster than scaling down the full resolution image.
+#[cfg(feature = "raw")]
+fn decode_raw(bytes: &[u8], settings: &LoadSettings) -> Result<image::RgbaImage> {
+	use imagepipe::{ImageSource, Pipeline};
+
+	let raw = rawloader::decode(&mut Cursor::new(bytes)).map_err(|err| err.to_string())?;
+	let mut pipeline = Pipeline::new_from_source(ImageSource::Raw(raw))?;
+	if let Some(max_dimension) = settings.max_dimension {
+		pipeline.globals.settings.maxwidth = max_dimension as usize;
+		pipeline.globals.settings.maxheight = max_dimension as usize;
+	}
+	let decoded = pipeline.output_8bit(None)?;
+	let image =
+		image::RgbImage::from_raw(decoded.width as u32, decoded.height as u32, decoded.data)
+			.ok_or("The developed RAW image is incomplete")?;
+	Ok(image::DynamicImage::ImageRgb8(image).into_rgba8())
+}
+
+/// Loads an image that is not stored in a file. The format is determined from the contents.
+pub fn load_image_from_memory(bytes: &[u8], settings: &LoadSettings) -> Result<image::RgbaImage> {
+	decode_image(bytes, image::guess_format(bytes)?, settings)
+}
+
+fn decode_image(
+	bytes: &[u8],
+	format: image::ImageFormat,
+	settings: &LoadSettings,
+) -> Result<image::RgbaImage> {
+	decode_image_with_color_type(bytes, format, settings).map(|(image, _)| image)
+}
+
+/// Same as `decode_image` but also returns the color type that was stored in the file
+fn decode_image_with_color_type(
+	bytes: &[u8],
+	format: image::ImageFormat,
+	settings: &LoadSettings,
+) -> Result<(image::RgbaImage, image::ColorType)> {
+	let image = match settings.max_decode_dimension {
+		Some(max_dimension) if format == image::ImageFormat::Jpeg => {
+			decode_scaled_jpeg(bytes, max_dimension)?
+		}
+		_ => image::load_from_memory_with_format(bytes, format)?,
+	};
+	let color_type = image.color();
+	let icc_profile = get_icc_profile(bytes, format);
+	let image =
+		correct_image(image.to_rgba8(), icc_profile.as_deref(), get_orientation(bytes), settings);
+	Ok((image, color_type))
+}
+
+/// Converts the decoded image to sRGB, turns it upright and applies the settings
+fn correct_image(
+	mut image: image::RgbaImage,
+	icc_profile: Option<&[u8]>,
+	orientation: u32,
+	settings: &LoadSettings,
+) -> image::RgbaImage {
+	if settings.color_management {
+		if let Some(profile) = icc_profile {
+			convert_to_srgb(&mut image, profile);
+		}
+	}
+	apply_settings(apply_orientation(image, orientation), settings)
+}
+
+/// Lets the decoder skip the detail that wouldn't fit in `max_dimension`. The returned image
+/// is at least that large, unless the JPEG itself is smaller.
+fn decode_scaled_jpeg(bytes: &[u8], max_dimension: u32) -> Result<image::DynamicImage> {
+	use image::ImageDecoder;
+
+	let mut decoder = JpegDecoder::new(Cursor::new(bytes))?;
+	let (width, height) = decoder.dimensions();
+	if width.max(height) > max_dimension {
+		let (scaled_width, scaled_height) = fitted_dimensions((width, height), max_dimension);
+		decoder.scale(scaled_width as u16, scaled_height as u16)?;
+	}
+	Ok(image::DynamicImage::from_decoder(decoder)?)
+}
+
+/// The dimensions `decode_scaled_jpeg` decodes a JPEG with these dimensions at. The decoder
+/// can only reduce the resolution to 1/2, 1/4 or 1/8, it picks the smallest of these that
+/// still reaches the fitted size in one direction.
+pub fn scaled_jpeg_dimensions(dimensions: (u32, u32), max_dimension: u32) -> (u32, u32) {
+	let (width, height) = dimensions;
+	let (fitted_width, fitted_height) = fitted_dimensions(dimensions, max_dimension);
+	if (fitted_width, fitted_height) == dimensions {
+		return dimensions;
+	}
+	// The scale is in eighths and the result is rounded up
+	let scaled = |length: u32, scale: u32| (length * scale).div_ceil(8);
+	for &scale in &[1, 2, 4] {
+		if scaled(width, scale) >= fitted_width || scaled(height, scale) >= fitted_height {
+			return (scaled(width, scale), scaled(height, scale));
+		}
+	}
+	dimensions
+}
+
+fn apply_settings(image: image::RgbaImage, settings: &LoadSettings) -> image::RgbaImage {
+	let mut image = match settings.max_dimension {
+		Some(max_dimension) => fit_to_dimension(image, max_dimension),
+		None => image,
+	};
+	if !settings.adjustment.is_identity() {
+		adjust_colors(&mut image, settings.adjustment, settings.srgb);
+	}
+	if settings.premultiply_alpha {
+		premultiply_alpha(&mut image, settings.srgb);
+	}
+	if !settings.srgb {
+		encode_linear_as_srgb(&mut image);
+	}
+	image
+}
+
+/// Applies the adjustment to the linear light values of the colors. The values are decoded
+/// first if they are sRGB encoded.
+fn adjust_colors(image: &mut image::RgbaImage, adjustment: ColorAdjustment, srgb: bool) {
+	let scale = 2f32.powf(adjustment.exposure);
+	let mut table = [0u8; 256];
+	for (value, adjusted) in table.iter_mut().enumerate() {
+		let value = value as f32 / 255.0;
+		let linear = if srgb { srgb_to_linear(value) } else { value };
+		let linear = (linear * scale).min(1.0).powf(1.0 / adjustment.gamma);
+		let value = if srgb { linear_to_srgb(linear) } else { linear };
+		*adjusted = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+	}
+	for pixel in image.pixels_mut() {
+		// The alpha channel is left as it is
+		for channel in pixel.0.iter_mut().take(3) {
+			*channel = table[*channel as usize];
+		}
+	}
+}
+
+/// Multiplies the colors by their alpha. The sRGB encoded colors are multiplied in linear
+/// space, because that's where the blending happens after the texture decoded them.
+fn premultiply_alpha(image: &mut image::RgbaImage, srgb: bool) {
+	// The premultiplied value of every value for every alpha
+	let mut table = vec![0u8; 256 * 256];
+	for alpha in 0..256 {
+		for value in 0..256 {
+			let factor = alpha as f32 / 255.0;
+			let color = value as f32 / 255.0;
+			let premultiplied =
+				if srgb { linear_to_srgb(srgb_to_linear(color) * factor) } else { color * factor };
+			table[alpha * 256 + value as usize] = (premultiplied * 255.0).round() as u8;
+		}
+	}
+	for pixel in image.pixels_mut() {
+		let row = pixel.0[3] as usize * 256;
+		for channel in pixel.0.iter_mut().take(3) {
+			*channel = table[row + *channel as usize];
+		}
+	}
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+	if value <= 0.040_45 {
+		value / 12.92
+	} else {
+		((value + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+fn linear_to_srgb(linear: f32) -> f32 {
+	if linear <= 0.003_130_8 {
+		linear * 12.92
+	} else {
+		1.055 * linear.powf(1.0 / 2.4) - 0.055
+	}
+}
+
+/// The textures are always sRGB textures which decode the colors when they are sampled. Encoding
+/// linear values beforehand makes sampling return the original values.
+fn encode_linear_as_srgb(image: &mut image::RgbaImage) {
+	let mut table = [0u8; 256];
+	for (value, encoded) in table.iter_mut().enumerate() {
+		let linear = value as f32 / 255.0;
+		let srgb = if linear <= 0.003_130_8 {
+			linear * 12.92
+		} else {
+			1.055 * linear.powf(1.0 / 2.4) - 0.055
+		};
+		*encoded = (srgb * 255.0).round() as u8;
+	}
+	for pixel in image.pixels_mut() {
+		// The alpha channel is always linear
+		for channel in pixel.0.iter_mut().take(3) {
+			*channel = table[*channel as usize];
+		}
+	}
+}
+
+/// Scales the image down so that neither its width nor its height is larger than
+/// `max_dimension`. Images that already fit are returned unchanged.
+pub fn fit_to_dimension(image: image::RgbaImage, max_dimension: u32) -> image::RgbaImage {
+	let dimensions = image.dimensions();
+	let (new_width, new_height) = fitted_dimensions(dimensions, max_dimension);
+	if (new_width, new_height) == dimensions {
+		return image;
+	}
+	image::imageops::resize(&image, new_width, new_height, image::imageops::FilterType::Triangle)
+}
+
+/// The dimensions `fit_to_dimension` scales an image with these dimensions to
+pub fn fitted_dimensions(dimensions: (u32, u32), max_dimension: u32) -> (u32, u32) {
+	let (width, height) = dimensions;
+	if width <= max_dimension && height <= max_dimension {
+		return dimensions;
+	}
+	let scale = max_dimension as f64 / width.max(height) as f64;
+	let new_width = ((width as f64 * scale).round() as u32).max(1);
+	let new_height = ((height as f64 * scale).round() as u32).max(1);
+	(new_width, new_height)
+}
+
+/// Returns the ICC profile embedded in the image. Only JPEG files are supported, `None` is
+/// returned for other formats.
+pub fn get_icc_profile(bytes: &[u8], format: image::ImageFormat) -> Option<Vec<u8>> {
+	const ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+
+	if format != image::ImageFormat::Jpeg {
+		return None;
+	}
+	// The profile may be split into multiple APP2 segments, each of them starts with the
+	// signature followed by the sequence number of the chunk and the total number of chunks
+	let mut chunks = Vec::new();
+	let mut pos = 2;
+	while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+		let marker = bytes[pos + 1];
+		// Start of scan, the metadata segments are all before this
+		if marker == 0xDA {
+			break;
+		}
+		let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+		let segment = bytes.get(pos + 4..pos + 2 + length)?;
+		if marker == 0xE2 && segment.starts_with(ICC_SIGNATURE) && segment.len() > 14 {
+			chunks.push((segment[12], &segment[14..]));
+		}
+		pos += 2 + length;
+	}
+	if chunks.is_empty() {
+		return None;
+	}
+	chunks.sort_by_key(|(sequence_number, _)| *sequence_number);
+	Some(chunks.into_iter().flat_map(|(_, data)| data.iter().copied()).collect())
+}
+
+/// Converts the colors of the image from the color space described by the ICC profile to
+/// sRGB. The image is left unchanged if the profile can't be parsed.
+pub fn convert_to_srgb(image: &mut image::RgbaImage, icc_profile: &[u8]) {
+	let input = match qcms::Profile::new_from_slice(icc_profile, false) {
+		Some(profile) => profile,
+		None => return,
+	};
+	let mut output = qcms::Profile::new_sRGB();
+	output.precache_output_transform();
+	let transform =
+		qcms::Transform::new(&input, &output, qcms::DataType::RGBA8, qcms::Intent::Perceptual);
+	if let Some(transform) = transform {
+		transform.apply(&mut *image);
+	}
+}
+
+/// Returns the value of the EXIF orientation tag, or of the XMP one if the image has no EXIF
+/// orientation. Returns 1 (meaning no transformation is needed) if neither is present.
+pub fn get_orientation(bytes: &[u8]) -> u32 {
+	let exif_orientation =
+		exif::Reader::new().read_from_container(&mut Cursor::new(bytes)).ok().and_then(|exif| {
+			let field = exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?;
+			field.value.get_uint(0)
+		});
+	exif_orientation.or_else(|| get_xmp_orientation(bytes)).unwrap_or(1)
+}
+
+/// Looks for the `tiff:Orientation` property in the XMP packet embedded in the file. Some
+/// cameras and scanners only store the orientation there.
+fn get_xmp_orientation(bytes: &[u8]) -> Option<u32> {
+	let start = find_bytes(bytes, b"<x:xmpmeta")?;
+	let packet = &bytes[start..];
+	let packet = &packet[..find_bytes(packet, b"</x:xmpmeta>").unwrap_or(packet.len())];
+
+	let property = find_bytes(packet, b"tiff:Orientation")?;
+	// The value is either an attribute, `tiff:Orientation="6"`, or the content of an element,
+	// `<tiff:Orientation>6</tiff:Orientation>`
+	let rest = &packet[property + b"tiff:Orientation".len()..];
+	let rest = match rest.first()? {
+		b'=' => rest.get(2..)?,
+		b'>' => &rest[1..],
+		_ => return None,
+	};
+	let digits = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+	let orientation = std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()?;
+	match orientation {
+		1..=8 => Some(orientation),
+		_ => None,
+	}
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Rotates and flips the image so that it appears upright, according to the
+/// EXIF orientation value.
+pub fn apply_orientation(image: image::RgbaImage, orientation: u32) -> image::RgbaImage {
+	use image::imageops;
+
+	match orientation {
+		2 => imageops::flip_horizontal(&image),
+		3 => imageops::rotate180(&image),
+		4 => imageops::flip_vertical(&image),
+		5 => imageops::flip_horizontal(&imageops::rotate90(&image)),
+		6 => imageops::rotate90(&image),
+		7 => imageops::flip_horizontal(&imageops::rotate270(&image)),
+		8 => imageops::rotate270(&image),
+		_ => image,
+	}
+}
+
+/// The maximum number of mipmap levels that are generated for a texture in addition to
+/// the full resolution level
+pub const MAX_MIPMAP_LEVELS: u32 = 4;
+
+/// `SrgbTexture2d` stores the textures in the SRGB8_ALPHA8 format
+const BYTES_PER_TEXEL: u64 = 4;
+
+/// Creates the textures of the decoded images. This is the only step of loading an image
+/// that needs a display, the cache entries work the same with any texture type.
+pub trait Uploader {
+	type Texture: TextureSize;
+
+	fn upload(&self, image: image::RgbaImage) -> Result<Self::Texture>;
+
+	#[cfg(feature = "compressed")]
+	fn upload_compressed(&self, image: &CompressedImage) -> Result<Self::Texture>;
+
+	/// Identifies the context that the textures are created in, the textures of different
+	/// contexts are kept apart by the cache. This is `None` if there is only ever one.
+	fn context(&self) -> Option<Rc<dyn Any>> {
+		None
+	}
+}
+
+/// The texture of an image uploaded to a display. GPU compressed textures stay compressed and
+/// are decompressed by the GPU whenever they are sampled.
+pub enum ImageTexture {
+	Srgb(SrgbTexture2d),
+	#[cfg(feature = "compressed")]
+	Compressed(CompressedSrgbTexture2d),
+}
+
+impl ImageTexture {
+	pub fn width(&self) -> u32 {
+		self.texture_dimensions().0
+	}
+
+	pub fn height(&self) -> u32 {
+		self.texture_dimensions().1
+	}
+}
+
+impl TextureSize for ImageTexture {
+	fn texture_dimensions(&self) -> (u32, u32) {
+		match self {
+			ImageTexture::Srgb(texture) => texture.texture_dimensions(),
+			#[cfg(feature = "compressed")]
+			ImageTexture::Compressed(texture) => (texture.width(), texture.height()),
+		}
+	}
+}
+
+impl Uploader for glium::Display {
+	type Texture = ImageTexture;
+
+	fn context(&self) -> Option<Rc<dyn Any>> {
+		use glium::backend::Facade;
+
+		Some(self.get_context().clone())
+	}
+
+	fn upload(&self, image: image::RgbaImage) -> Result<ImageTexture> {
+		Ok(ImageTexture::Srgb(texture_from_image(self, image)?))
+	}
+
+	#[cfg(feature = "compressed")]
+	fn upload_compressed(&self, image: &CompressedImage) -> Result<ImageTexture> {
+		Ok(ImageTexture::Compressed(compressed::upload(self, image)?))
+	}
+}
+
+/// The width and height of an uploaded texture, which its size estimate is based on
+pub trait TextureSize {
+	fn texture_dimensions(&self) -> (u32, u32);
+}
+
+impl TextureSize for SrgbTexture2d {
+	fn texture_dimensions(&self) -> (u32, u32) {
+		(self.width(), self.height())
+	}
+}
+
+/// If the image is larger than the maximal texture size, the texture is created once more
+/// with half the resolution. The returned texture may be smaller than the image because of
+/// this, so the size of the texture must be estimated from its own dimensions.
 pub fn texture_from_image(
 	display: &glium::Display,
 	image: image::RgbaImage,
 ) -> Result<SrgbTexture2d> {
+	use glium::texture::TextureCreationError;
+
+	match create_texture(display, &image) {
+		Err(TextureCreationError::DimensionsNotSupported) => {
+			let (width, height) = image.dimensions();
+			let image = fit_to_dimension(image, (width.max(height) / 2).max(1));
+			Ok(create_texture(display, &image)?)
+		}
+		result => Ok(result?),
+	}
+}
+
+fn create_texture(
+	display: &glium::Display,
+	image: &image::RgbaImage,
+) -> std::result::Result<SrgbTexture2d, glium::texture::TextureCreationError> {
 	let image_dimensions = image.dimensions();
-	let image = RawImage2d::from_raw_rgba(image.into_raw(), image_dimensions);
+	let raw_image = RawImage2d {
+		data: Cow::Borrowed(image.as_raw().as_slice()),
+		width: image_dimensions.0,
+		height: image_dimensions.1,
+		format: ClientFormat::U8U8U8U8,
+	};
+
+	let mipmaps = match get_mipmap_levels(image_dimensions) {
+		0 => glium::texture::MipmapsOption::NoMipmap,
+		levels => glium::texture::MipmapsOption::AutoGeneratedMipmapsMax(levels),
+	};
+
+	SrgbTexture2d::with_mipmaps(display, raw_image, mipmaps)
+}
+
+/// Copies a mipmap level of the texture into a texture of its own, which is half as wide and
+/// half as tall as the previous level. Level 0 is the full resolution.
+pub fn copy_mipmap_level(
+	display: &glium::Display,
+	texture: &SrgbTexture2d,
+	level: u32,
+) -> Result<SrgbTexture2d> {
+	use glium::framebuffer::SimpleFrameBuffer;
+	use glium::uniforms::MagnifySamplerFilter;
+	use glium::Surface;
+
+	let mipmap = match texture.mipmap(level) {
+		Some(mipmap) => mipmap,
+		None => bail!(format!(
+			"The texture has no mipmap level {}, it has {} levels",
+			level,
+			texture.get_mipmap_levels()
+		)),
+	};
+	let copy = SrgbTexture2d::empty_with_mipmaps(
+		display,
+		glium::texture::MipmapsOption::NoMipmap,
+		mipmap.width(),
+		mipmap.height(),
+	)?;
+	let source =
+		SimpleFrameBuffer::new(display, mipmap).chain_err(|| "Failed to read the mipmap level")?;
+	let target =
+		SimpleFrameBuffer::new(display, &copy).chain_err(|| "Failed to write the mipmap copy")?;
+	source.fill(&target, MagnifySamplerFilter::Nearest);
+	Ok(copy)
+}
+
+/// Returns the number of mipmap levels generated for a texture of the given size,
+/// not counting the full resolution level.
+pub fn get_mipmap_levels(dimensions: (u32, u32)) -> u32 {
+	if dimensions.0 <= 1 || dimensions.1 <= 1 {
+		return 0;
+	}
+	// Every level halves the size, so there can't be more levels than log2 of the larger side
+	let max_dimension = dimensions.0.max(dimensions.1);
+	let max_levels = 31 - max_dimension.leading_zeros();
+	max_levels.min(MAX_MIPMAP_LEVELS)
+}
+
+/// Returns the number of bytes the texture created from an image of this size
+/// occupies on the GPU, including all of its mipmap levels.
+pub fn get_image_size_estimate(dimensions: (u32, u32)) -> u32 {
+	let mut texel_count = 0;
+	for level in 0..=get_mipmap_levels(dimensions) {
+		let width = (dimensions.0 >> level).max(1) as u64;
+		let height = (dimensions.1 >> level).max(1) as u64;
+		texel_count += width * height;
+	}
+	(texel_count * BYTES_PER_TEXEL).min(u32::MAX as u64) as u32
+}
+
+/// The extensions that are considered to be supported images when there's no
+/// other list specified.
+pub const DEFAULT_SUPPORTED_EXTENSIONS: &[&str] = &[
+	"jpg", "jpeg", "png", "gif", "webp", "tif", "tiff", "tga", "bmp", "ico", "hdr", "pbm", "pam",
+	"ppm", "pgm",
+];
+
+/// The extensions of the HEIF images that are decoded by libheif. This is empty unless the
+/// `heic` feature is enabled.
+#[cfg(feature = "heic")]
+pub const HEIF_EXTENSIONS: &[&str] = &["heic", "heif"];
+#[cfg(not(feature = "heic"))]
+pub const HEIF_EXTENSIONS: &[&str] = &[];
+
+#[cfg(feature = "raw")]
+pub const RAW_EXTENSIONS: &[&str] = &[
+	"3fr", "arw", "cr2", "crw", "dcr", "dng", "erf", "kdc", "mef", "mos", "mrw", "nef", "nrw",
+	"orf", "pef", "raf", "rw2", "sr2", "srf", "srw",
+];
+#[cfg(not(feature = "raw"))]
+pub const RAW_EXTENSIONS: &[&str] = &[];
+
+/// The extensions of the SVG images. This is empty unless the `svg` feature is enabled.
+#[cfg(feature = "svg")]
+pub const SVG_EXTENSIONS: &[&str] = &["svg"];
+#[cfg(not(feature = "svg"))]
+pub const SVG_EXTENSIONS: &[&str] = &[];
+
+/// The extensions of the files that contain GPU compressed textures. This is empty unless the
+/// `compressed` feature is enabled.
+#[cfg(feature = "compressed")]
+pub const COMPRESSED_EXTENSIONS: &[&str] = &["dds", "ktx"];
+#[cfg(not(feature = "compressed"))]
+pub const COMPRESSED_EXTENSIONS: &[&str] = &[];
+
+/// # Arguments
+/// * `extensions` - The supported extensions, all of them in lowercase
+pub fn is_file_supported(filename: &Path, extensions: &BTreeSet<String>) -> bool {
+	if let Some(ext) = filename.extension() {
+		if let Some(ext) = ext.to_str() {
+			return extensions.contains(&ext.to_lowercase());
+		}
+	}
+
+	false
+}
+
+/// Tells if the file starts like an image in one of the formats that the `image` crate can
+/// decode. Only the first few bytes of the file are read.
+pub fn has_image_signature(path: &Path) -> bool {
+	use std::io::Read;
+
+	// Enough for every signature that `image` recognizes
+	const SIGNATURE_LEN: u64 = 16;
+
+	let header = if archive::split_archive_path(path).is_some() {
+		archive::read(path)
+	} else {
+		let mut header = Vec::with_capacity(SIGNATURE_LEN as usize);
+		fs::File::open(path)
+			.and_then(|file| file.take(SIGNATURE_LEN).read_to_end(&mut header))
+			.map(|_| header)
+	};
+	match header {
+		Ok(header) => image::guess_format(header.as_slice()).is_ok(),
+		Err(_) => false,
+	}
+}
+
+/// The filtering that suits the image best when it's magnified
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SamplingHint {
+	Linear,
+	/// Keeps the pixels sharp, for pixel art
+	Nearest,
+}
+
+impl SamplingHint {
+	/// The largest number of pixels that an image may have to be considered pixel art
+	const MAX_PIXEL_ART_AREA: u32 = 256 * 256;
+	/// The largest number of distinct colors in an image that is considered pixel art
+	const MAX_PIXEL_ART_COLORS: usize = 256;
+
+	/// Small images with only a few colors, like the ones with an indexed palette, are most
+	/// likely pixel art
+	pub fn for_image(image: &image::RgbaImage) -> SamplingHint {
+		if image.width() * image.height() > Self::MAX_PIXEL_ART_AREA {
+			return SamplingHint::Linear;
+		}
+		let mut colors = std::collections::HashSet::new();
+		for pixel in image.pixels() {
+			colors.insert(pixel.0);
+			if colors.len() > Self::MAX_PIXEL_ART_COLORS {
+				return SamplingHint::Linear;
+			}
+		}
+		SamplingHint::Nearest
+	}
+}
+
+pub struct TextureEntry<T = ImageTexture> {
+	pub texture: Rc<T>,
+	/// The last time this texture was loaded or returned from the cache
+	pub last_access: Instant,
+	pub sampling: SamplingHint,
+	/// The decoded image that the texture was created from, only kept on request
+	pub pixels: Option<Rc<image::RgbaImage>>,
+	pub info: Option<ImageInfo>,
+	/// The hash of the contents of the file, only computed with content hashing
+	pub content_hash: Option<u64>,
+}
+
+impl<T: TextureSize> TextureEntry<T> {
+	pub fn new(texture: Rc<T>) -> TextureEntry<T> {
+		TextureEntry {
+			texture,
+			last_access: Instant::now(),
+			sampling: SamplingHint::Linear,
+			pixels: None,
+			info: None,
+			content_hash: None,
+		}
+	}
+
+	pub fn size_estimate(&self) -> isize {
+		get_image_size_estimate(self.texture.texture_dimensions()) as isize
+			+ self.pixels_size_estimate()
+	}
+
+	/// The number of bytes taken up by the kept pixels
+	pub fn pixels_size_estimate(&self) -> isize {
+		self.pixels.as_ref().map_or(0, |pixels| pixels.as_raw().len() as isize)
+	}
+}
+
+pub struct AnimationEntry<T = ImageTexture> {
+	pub frames: Vec<(Rc<T>, Duration)>,
+	/// The last time this animation was loaded or returned from the cache
+	pub last_access: Instant,
+	/// Determined from the first frame
+	pub sampling: SamplingHint,
+	pub info: Option<ImageInfo>,
+	/// The hash of the contents of the file, only computed with content hashing
+	pub content_hash: Option<u64>,
+}
+
+/// Every page of a document uploaded as a separate texture
+pub struct PagesEntry<T = ImageTexture> {
+	pub pages: Vec<Rc<T>>,
+	/// The last time this document was loaded or returned from the cache
+	pub last_access: Instant,
+	/// Determined from the first page
+	pub sampling: SamplingHint,
+	pub info: Option<ImageInfo>,
+	/// The hash of the contents of the file, only computed with content hashing
+	pub content_hash: Option<u64>,
+}
+
+impl<T: TextureSize> PagesEntry<T> {
+	pub fn size_estimate(&self) -> isize {
+		self.pages
+			.iter()
+			.map(|texture| get_image_size_estimate(texture.texture_dimensions()) as isize)
+			.sum()
+	}
+}
+
+impl<T: TextureSize> AnimationEntry<T> {
+	pub fn new(frames: Vec<(Rc<T>, Duration)>) -> AnimationEntry<T> {
+		AnimationEntry {
+			frames,
+			last_access: Instant::now(),
+			sampling: SamplingHint::Linear,
+			info: None,
+			content_hash: None,
+		}
+	}
+
+	pub fn size_estimate(&self) -> isize {
+		self.frames
+			.iter()
+			.map(|(texture, _)| get_image_size_estimate(texture.texture_dimensions()) as isize)
+			.sum()
+	}
+}
+
+// The textures are shared, so the entries can be cloned whether or not the textures can
+impl<T> Clone for TextureEntry<T> {
+	fn clone(&self) -> Self {
+		TextureEntry {
+			texture: self.texture.clone(),
+			last_access: self.last_access,
+			sampling: self.sampling,
+			pixels: self.pixels.clone(),
+			info: self.info,
+			content_hash: self.content_hash,
+		}
+	}
+}
+
+impl<T> Clone for AnimationEntry<T> {
+	fn clone(&self) -> Self {
+		AnimationEntry {
+			frames: self.frames.clone(),
+			last_access: self.last_access,
+			sampling: self.sampling,
+			info: self.info,
+			content_hash: self.content_hash,
+		}
+	}
+}
+
+impl<T> Clone for PagesEntry<T> {
+	fn clone(&self) -> Self {
+		PagesEntry {
+			pages: self.pages.clone(),
+			last_access: self.last_access,
+			sampling: self.sampling,
+			info: self.info,
+			content_hash: self.content_hash,
+		}
+	}
+}
+
+pub enum CachedTexture<T = ImageTexture> {
+	Texture((fs::Metadata, TextureEntry<T>)),
+	/// Every frame of an animated image uploaded as a separate texture
+	Animation((fs::Metadata, AnimationEntry<T>)),
+	Pages((fs::Metadata, PagesEntry<T>)),
+	/// A GPU compressed texture, its size depends on the format of the blocks
+	#[cfg(feature = "compressed")]
+	Compressed((fs::Metadata, TextureEntry<T>, CompressedSrgbFormat)),
+	/// An image that was decoded from memory and has no file associated with it
+	InMemory(TextureEntry<T>),
+	LoadRequested,
+}
+
+impl<T> Clone for CachedTexture<T> {
+	fn clone(&self) -> Self {
+		match self {
+			CachedTexture::Texture((metadata, entry)) => {
+				CachedTexture::Texture((metadata.clone(), entry.clone()))
+			}
+			CachedTexture::Animation((metadata, entry)) => {
+				CachedTexture::Animation((metadata.clone(), entry.clone()))
+			}
+			CachedTexture::Pages((metadata, entry)) => {
+				CachedTexture::Pages((metadata.clone(), entry.clone()))
+			}
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((metadata, entry, format)) => {
+				CachedTexture::Compressed((metadata.clone(), entry.clone(), *format))
+			}
+			CachedTexture::InMemory(entry) => CachedTexture::InMemory(entry.clone()),
+			CachedTexture::LoadRequested => CachedTexture::LoadRequested,
+		}
+	}
+}
+
+impl<T: TextureSize> CachedTexture<T> {
+	/// Uploads the decoded image with the uploader. The pixels of still images are also kept in
+	/// the entry if `keep_pixels` is true.
+	pub fn from_decoded<U: Uploader<Texture = T>>(
+		uploader: &U,
+		metadata: fs::Metadata,
+		image: DecodedImage,
+		keep_pixels: bool,
+	) -> Result<CachedTexture<T>> {
+		let kept_pixels = |image: &image::RgbaImage| {
+			if keep_pixels {
+				Some(Rc::new(image.clone()))
+			} else {
+				None
+			}
+		};
+		Ok(match image {
+			DecodedImage::Still(image) => {
+				let sampling = SamplingHint::for_image(&image);
+				let pixels = kept_pixels(&image);
+				let texture = Rc::new(uploader.upload(image)?);
+				CachedTexture::Texture((
+					metadata,
+					TextureEntry { sampling, pixels, ..TextureEntry::new(texture) },
+				))
+			}
+			DecodedImage::Animation(frames) => {
+				let sampling = match frames.first() {
+					Some((image, _)) => SamplingHint::for_image(image),
+					None => SamplingHint::Linear,
+				};
+				let mut textures = Vec::with_capacity(frames.len());
+				for (image, delay) in frames {
+					textures.push((Rc::new(uploader.upload(image)?), delay));
+				}
+				let entry = AnimationEntry { sampling, ..AnimationEntry::new(textures) };
+				CachedTexture::Animation((metadata, entry))
+			}
+			DecodedImage::Pages(pages) => {
+				let sampling = match pages.first() {
+					Some(image) => SamplingHint::for_image(image),
+					None => SamplingHint::Linear,
+				};
+				let mut textures = Vec::with_capacity(pages.len());
+				for image in pages {
+					textures.push(Rc::new(uploader.upload(image)?));
+				}
+				let entry = PagesEntry {
+					pages: textures,
+					last_access: Instant::now(),
+					sampling,
+					info: None,
+					content_hash: None,
+				};
+				CachedTexture::Pages((metadata, entry))
+			}
+			#[cfg(feature = "compressed")]
+			DecodedImage::Compressed(image) => {
+				let texture = Rc::new(uploader.upload_compressed(&image)?);
+				CachedTexture::Compressed((metadata, TextureEntry::new(texture), image.format))
+			}
+		})
+	}
+
+	/// Returns the texture that should be displayed for this entry. This is the first frame
+	/// in case of animations.
+	pub fn texture(&self) -> Option<Rc<T>> {
+		match self {
+			CachedTexture::Texture((_, entry)) | CachedTexture::InMemory(entry) => {
+				Some(entry.texture.clone())
+			}
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, _)) => Some(entry.texture.clone()),
+			CachedTexture::Animation((_, entry)) => {
+				entry.frames.first().map(|(texture, _)| texture.clone())
+			}
+			CachedTexture::Pages((_, entry)) => entry.pages.first().cloned(),
+			CachedTexture::LoadRequested => None,
+		}
+	}
+
+	/// The number of pages of a document, every other entry has a single page
+	pub fn page_count(&self) -> usize {
+		match self {
+			CachedTexture::Pages((_, entry)) => entry.pages.len(),
+			CachedTexture::LoadRequested => 0,
+			_ => 1,
+		}
+	}
+
+	/// The texture of the page at `index`. The only page of the entries that aren't documents
+	/// is the one returned by `texture`.
+	pub fn page(&self, index: usize) -> Option<Rc<T>> {
+		match self {
+			CachedTexture::Pages((_, entry)) => entry.pages.get(index).cloned(),
+			_ if index == 0 => self.texture(),
+			_ => None,
+		}
+	}
+
+	/// What the file was decoded from, if it's known
+	pub fn info(&self) -> Option<ImageInfo> {
+		match self {
+			CachedTexture::Texture((_, entry)) => entry.info,
+			CachedTexture::Animation((_, entry)) => entry.info,
+			CachedTexture::Pages((_, entry)) => entry.info,
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, _)) => entry.info,
+			_ => None,
+		}
+	}
 
-	let mipmaps = if image_dimensions.0 == 1 || image_dimensions.1 == 1 {
-		glium::texture::MipmapsOption::NoMipmap
-	} else {
-		glium::texture::MipmapsOption::AutoGeneratedMipmapsMax(4)
-	};
+	/// Stores what the file was decoded from with the entry
+	pub fn set_info(&mut self, info: Option<ImageInfo>) {
+		match self {
+			CachedTexture::Texture((_, entry)) => entry.info = info,
+			CachedTexture::Animation((_, entry)) => entry.info = info,
+			CachedTexture::Pages((_, entry)) => entry.info = info,
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, _)) => entry.info = info,
+			_ => (),
+		}
+	}
+
+	/// The hash of the contents of the file when the texture was loaded, if it was computed
+	pub fn content_hash(&self) -> Option<u64> {
+		match self {
+			CachedTexture::Texture((_, entry)) => entry.content_hash,
+			CachedTexture::Animation((_, entry)) => entry.content_hash,
+			CachedTexture::Pages((_, entry)) => entry.content_hash,
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, _)) => entry.content_hash,
+			_ => None,
+		}
+	}
+
+	/// Stores the hash of the contents of the file with the entry
+	pub fn set_content_hash(&mut self, content_hash: Option<u64>) {
+		match self {
+			CachedTexture::Texture((_, entry)) => entry.content_hash = content_hash,
+			CachedTexture::Animation((_, entry)) => entry.content_hash = content_hash,
+			CachedTexture::Pages((_, entry)) => entry.content_hash = content_hash,
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, _)) => entry.content_hash = content_hash,
+			_ => (),
+		}
+	}
+
+	/// The decoded pixels of a still image if they were kept
+	pub fn pixels(&self) -> Option<&image::RgbaImage> {
+		match self {
+			CachedTexture::Texture((_, entry)) | CachedTexture::InMemory(entry) => {
+				entry.pixels.as_deref()
+			}
+			_ => None,
+		}
+	}
+
+	pub fn sampling(&self) -> Option<SamplingHint> {
+		match self {
+			CachedTexture::Texture((_, entry)) | CachedTexture::InMemory(entry) => {
+				Some(entry.sampling)
+			}
+			CachedTexture::Animation((_, entry)) => Some(entry.sampling),
+			CachedTexture::Pages((_, entry)) => Some(entry.sampling),
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, _)) => Some(entry.sampling),
+			CachedTexture::LoadRequested => None,
+		}
+	}
+
+	/// Same as `texture` but borrows the texture instead of cloning the `Rc`
+	pub fn texture_ref(&self) -> Option<&T> {
+		match self {
+			CachedTexture::Texture((_, entry)) | CachedTexture::InMemory(entry) => {
+				Some(&entry.texture)
+			}
+			CachedTexture::Animation((_, entry)) => {
+				entry.frames.first().map(|(texture, _)| texture.as_ref())
+			}
+			CachedTexture::Pages((_, entry)) => entry.pages.first().map(|texture| texture.as_ref()),
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, _)) => Some(&entry.texture),
+			CachedTexture::LoadRequested => None,
+		}
+	}
+
+	/// The metadata of the file at the time it was loaded
+	pub fn metadata(&self) -> Option<&fs::Metadata> {
+		match self {
+			CachedTexture::Texture((metadata, _))
+			| CachedTexture::Animation((metadata, _))
+			| CachedTexture::Pages((metadata, _)) => Some(metadata),
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((metadata, _, _)) => Some(metadata),
+			CachedTexture::InMemory(_) | CachedTexture::LoadRequested => None,
+		}
+	}
+
+	pub fn last_access(&self) -> Option<Instant> {
+		match self {
+			CachedTexture::Texture((_, entry)) | CachedTexture::InMemory(entry) => {
+				Some(entry.last_access)
+			}
+			CachedTexture::Animation((_, entry)) => Some(entry.last_access),
+			CachedTexture::Pages((_, entry)) => Some(entry.last_access),
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, _)) => Some(entry.last_access),
+			CachedTexture::LoadRequested => None,
+		}
+	}
 
-	Ok(SrgbTexture2d::with_mipmaps(display, image, mipmaps)?)
+	/// Marks the entry as accessed right now
+	pub fn touch(&mut self) {
+		let now = Instant::now();
+		match self {
+			CachedTexture::Texture((_, entry)) | CachedTexture::InMemory(entry) => {
+				entry.last_access = now;
+			}
+			CachedTexture::Animation((_, entry)) => entry.last_access = now,
+			CachedTexture::Pages((_, entry)) => entry.last_access = now,
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, _)) => entry.last_access = now,
+			CachedTexture::LoadRequested => {}
+		}
+	}
+
+	/// The estimated number of bytes this entry takes up in the video memory
+	pub fn size_estimate(&self) -> isize {
+		match self {
+			CachedTexture::Texture((_, entry)) | CachedTexture::InMemory(entry) => {
+				entry.size_estimate()
+			}
+			CachedTexture::Animation((_, entry)) => entry.size_estimate(),
+			CachedTexture::Pages((_, entry)) => entry.size_estimate(),
+			#[cfg(feature = "compressed")]
+			CachedTexture::Compressed((_, entry, format)) => {
+				let (width, height) = entry.texture.texture_dimensions();
+				compressed::block_data_size(*format, width, height) as isize
+			}
+			CachedTexture::LoadRequested => 0,
+		}
+	}
 }
 
-pub fn get_image_size_estimate(dimensions: (u32, u32)) -> u32 {
-	// counting all the mipmaps would add an additionnal multiplier of around ~1.6
-	// but only the gpu textures have mip maps so just multiply by 1.5
-	((dimensions.0 * dimensions.1 * 4) as f32 * 1.5) as u32
+/// Returns the key that an image loaded from memory is stored under in the texture cache.
+///
+/// The key contains a path separator so that it can never be the same as the name of a file.
+pub fn memory_cache_key(key: &str) -> OsString {
+	OsString::from(format!("<memory>/{}", key))
 }
 
-pub fn is_file_supported(filename: &Path) -> bool {
-	if let Some(ext) = filename.extension() {
-		if let Some(ext) = ext.to_str() {
-			let ext = ext.to_lowercase();
-			match ext.as_str() {
-				"jpg" | "jpeg" | "png" | "gif" | "webp" | "tif" | "tiff" | "tga" | "bmp"
-				| "ico" | "hdr" | "pbm" | "pam" | "ppm" | "pgm" => {
-					return true;
-				}
-				_ => (),
+/// `generation` is the value of the generation counter when the image was requested
+pub enum LoadResult {
+	Ok {
+		path: PathBuf,
+		metadata: fs::Metadata,
+		image: DecodedImage,
+		info: Option<ImageInfo>,
+		content_hash: Option<u64>,
+		generation: usize,
+		/// The time it took to load and decode the file
+		decode_time: Duration,
+	},
+	Failed {
+		path: PathBuf,
+		error: Error,
+		generation: usize,
+	},
+	/// The request was cancelled before the thread got to it
+	Cancelled {
+		path: PathBuf,
+		generation: usize,
+	},
+}
+
+struct LoadRequest {
+	path: PathBuf,
+	generation: usize,
+}
+
+/// The scheduling priority of the threads that decode the prefetched images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThreadPriority {
+	#[default]
+	Normal,
+	/// Lets the decoding yield to the rest of the system, so that prefetching doesn't make
+	/// the machine sluggish
+	Low,
+}
+
+/// Lowers the priority of the calling thread. On Linux the niceness is a property of the
+/// thread, so this doesn't affect the rest of the process.
+#[cfg(target_os = "linux")]
+fn lower_current_thread_priority() -> io::Result<()> {
+	let result = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, 10) };
+	if result != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+/// The niceness applies to the whole process on macOS, so the thread is moved to the utility
+/// quality of service class instead, which is meant for long running background work.
+#[cfg(target_os = "macos")]
+fn lower_current_thread_priority() -> io::Result<()> {
+	let result =
+		unsafe { libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_UTILITY, 0) };
+	// The error number is returned instead of being stored in errno
+	if result != 0 {
+		return Err(io::Error::from_raw_os_error(result));
+	}
+	Ok(())
+}
+
+#[cfg(windows)]
+fn lower_current_thread_priority() -> io::Result<()> {
+	use winapi::um::processthreadsapi::{GetCurrentThread, SetThreadPriority};
+	use winapi::um::winbase::THREAD_PRIORITY_BELOW_NORMAL;
+
+	let result =
+		unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL as _) };
+	if result == 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn lower_current_thread_priority() -> io::Result<()> {
+	Ok(())
+}
+
+/// The requests waiting for the loader threads. The requests are sent to a shared queue,
+/// from which the threads take them in batches into queues of their own. A thread that has
+/// run out of requests takes them from the queues of the other threads, so that a thread
+/// doesn't sit idle while another one has a long list of requests to get through.
+#[derive(Default)]
+struct RequestQueue {
+	injector: Injector<LoadRequest>,
+	/// Take the requests from the queues of the individual threads
+	stealers: RwLock<Vec<(thread::ThreadId, Stealer<LoadRequest>)>>,
+	/// The number of requests that were sent but haven't been taken by a thread yet
+	queued: AtomicUsize,
+	closed: AtomicBool,
+	lock: Mutex<()>,
+	available: Condvar,
+}
+
+impl RequestQueue {
+	fn push(&self, request: LoadRequest) {
+		self.injector.push(request);
+		self.queued.fetch_add(1, Ordering::AcqRel);
+		// Taking the lock makes sure that a thread which is about to wait sees the request
+		drop(self.lock.lock().unwrap_or_else(PoisonError::into_inner));
+		self.available.notify_one();
+	}
+
+	/// Wakes up all the threads, so that the ones that should stop notice it
+	fn notify_all(&self) {
+		drop(self.lock.lock().unwrap_or_else(PoisonError::into_inner));
+		self.available.notify_all();
+	}
+
+	fn close(&self) {
+		self.closed.store(true, Ordering::Release);
+		self.notify_all();
+	}
+
+	/// Waits for the next request. Returns `None` once the queue is closed or `keep_waiting`
+	/// returns false.
+	fn pop<F: Fn() -> bool>(
+		&self,
+		local: &Worker<LoadRequest>,
+		keep_waiting: F,
+	) -> Option<LoadRequest> {
+		loop {
+			if self.closed.load(Ordering::Acquire) || !keep_waiting() {
+				return None;
+			}
+			if let Some(request) = self.find_request(local) {
+				self.queued.fetch_sub(1, Ordering::AcqRel);
+				return Some(request);
+			}
+			let guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+			if self.queued.load(Ordering::Acquire) == 0 {
+				// The timeout lets the thread notice that it was told to stop
+				let _ = self.available.wait_timeout(guard, Duration::from_millis(50));
+			} else {
+				// Another thread is moving the requests between the queues
+				drop(guard);
+				thread::yield_now();
 			}
 		}
 	}
 
-	false
+	fn find_request(&self, local: &Worker<LoadRequest>) -> Option<LoadRequest> {
+		local.pop().or_else(|| loop {
+			let steal = self.injector.steal_batch_and_pop(local).or_else(|| {
+				let stealers = self.stealers.read().unwrap_or_else(PoisonError::into_inner);
+				stealers.iter().map(|(_, stealer)| stealer.steal()).collect()
+			});
+			match steal {
+				Steal::Success(request) => break Some(request),
+				Steal::Empty => break None,
+				Steal::Retry => (),
+			}
+		})
+	}
+
+	fn add_thread(&self, local: &Worker<LoadRequest>) {
+		self.stealers
+			.write()
+			.unwrap_or_else(PoisonError::into_inner)
+			.push((thread::current().id(), local.stealer()));
+	}
+
+	/// Hands the requests of a stopping thread over to the rest of the threads
+	fn remove_thread(&self, local: Worker<LoadRequest>) {
+		let id = thread::current().id();
+		self.stealers
+			.write()
+			.unwrap_or_else(PoisonError::into_inner)
+			.retain(|(other, _)| *other != id);
+		while let Some(request) = local.pop() {
+			self.injector.push(request);
+		}
+		self.notify_all();
+	}
 }
 
-pub enum CachedTexture {
-	Texture((fs::Metadata, Rc<SrgbTexture2d>)),
-	LoadRequested,
+/// A running loader thread
+struct WorkerThread {
+	/// Tells the thread to stop after the request it's processing
+	stopping: Arc<AtomicBool>,
+	handle: thread::JoinHandle<()>,
 }
 
-pub enum LoadResult {
-	Ok { path: PathBuf, metadata: fs::Metadata, image: image::RgbaImage },
-	Failed,
+impl WorkerThread {
+	/// Tells the thread to exit without waiting for it, the returned handle is joined later.
+	/// The requests that the thread hasn't started are left for the other threads.
+	fn stop(self, queue: &RequestQueue) -> thread::JoinHandle<()> {
+		self.stopping.store(true, Ordering::Release);
+		queue.notify_all();
+		self.handle
+	}
+}
+
+/// Bounds the number of decoded images that are waiting to be uploaded by the main thread, so
+/// that the threads can't fill the memory with images faster than they are uploaded.
+#[derive(Default)]
+struct DecodedLimit {
+	/// The number of images that are being decoded or have been decoded but not received yet,
+	/// and the limit of that number
+	state: Mutex<(usize, Option<usize>)>,
+	released: Condvar,
+}
+
+impl DecodedLimit {
+	/// Waits until there's room for one more image and then takes it. The room is taken
+	/// regardless of the limit once `keep_waiting` returns false.
+	fn reserve<F: Fn() -> bool>(&self, keep_waiting: F) {
+		let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		while state.1.is_some_and(|limit| state.0 >= limit) && keep_waiting() {
+			// The timeout lets the thread notice that it was told to stop
+			state = self
+				.released
+				.wait_timeout(state, Duration::from_millis(50))
+				.unwrap_or_else(PoisonError::into_inner)
+				.0;
+		}
+		state.0 += 1;
+	}
+
+	fn release(&self) {
+		let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		state.0 = state.0.saturating_sub(1);
+		self.released.notify_one();
+	}
+
+	fn set_limit(&self, limit: Option<usize>) {
+		self.state.lock().unwrap_or_else(PoisonError::into_inner).1 = limit;
+		self.released.notify_all();
+	}
+}
+
+/// Waits for a loader thread to exit. A thread that panicked is only reported.
+fn join_worker(handle: thread::JoinHandle<()>) {
+	if let Err(err) = handle.join() {
+		eprintln!("Error occured while joining handle {:?}", err);
+	}
 }
 
 pub struct ImageLoader {
 	running: Arc<AtomicBool>,
-	join_handles: Option<Vec<thread::JoinHandle<()>>>,
 	image_rx: Receiver<LoadResult>,
-	path_tx: Sender<PathBuf>,
+	/// Given to the threads that are started later
+	loaded_img_tx: Sender<LoadResult>,
+	request_queue: Arc<RequestQueue>,
+	workers: Vec<WorkerThread>,
+	/// The threads that were told to stop but may still be finishing their last request
+	stopped: Vec<thread::JoinHandle<()>>,
+	/// The priority of the threads that are started later
+	thread_priority: ThreadPriority,
+	settings: Arc<Mutex<LoadSettings>>,
+	decoder: Arc<Mutex<Arc<dyn Decoder>>>,
+	/// Incremented every time the pending requests are cancelled. Requests made before the
+	/// last cancellation are skipped by the threads.
+	generation: Arc<AtomicUsize>,
+	decoded: Arc<DecodedLimit>,
 }
 
 impl ImageLoader {
+	/// The number of requests per thread that may wait to be processed. Requests beyond this
+	/// are refused, so that a quick series of navigations can't pile up work that is no
+	/// longer relevant by the time the threads get to it.
+	pub const REQUEST_QUEUE_DEPTH: usize = 16;
+
 	/// # Arguemnts
-	/// * `capacity` - Number of bytes. The last image loaded will be the one at which the allocated memory reaches or exceeds capacity
-	pub fn new(threads: u32) -> ImageLoader {
+	/// * `threads` - Number of threads that decode the images in the background
+	/// * `thread_priority` - The scheduling priority of those threads
+	///
+	/// Fails in the same cases as `set_thread_count`.
+	pub fn new(threads: u32, thread_priority: ThreadPriority) -> Result<ImageLoader> {
 		let running = Arc::new(AtomicBool::from(true));
 		//let loader_cache = HashMap::new();
 
-		let (load_request_tx, load_request_rx) = channel();
-		let load_request_rx = Arc::new(Mutex::new(load_request_rx));
-
 		let (loaded_img_tx, loaded_img_rx) = channel();
+		let settings = Arc::new(Mutex::new(LoadSettings::default()));
+		let generation = Arc::new(AtomicUsize::new(0));
 
-		let mut join_handles = Vec::new();
-		for _ in 0..threads {
-			let running = running.clone();
-			let load_request_rx = load_request_rx.clone();
-			let loaded_img_tx = loaded_img_tx.clone();
-
-			join_handles.push(thread::spawn(move || {
-				Self::thread_loop(running, load_request_rx, loaded_img_tx);
-			}));
-		}
-
-		ImageLoader {
+		let mut loader = ImageLoader {
 			//curr_dir: PathBuf::new(),
 			//curr_est_size: capacity as usize,
 			running,
@@ -119,68 +1996,727 @@ impl ImageLoader {
 			//total_capacity: capacity,
 			//loader_cache,
 			//texture_cache: BTreeMap::new(),
-			join_handles: Some(join_handles),
-
 			image_rx: loaded_img_rx,
-			path_tx: load_request_tx,
+			loaded_img_tx,
+			request_queue: Arc::new(RequestQueue::default()),
+			workers: Vec::new(),
+			stopped: Vec::new(),
+			thread_priority,
+			settings,
+			decoder: Arc::new(Mutex::new(Arc::new(FileDecoder))),
+			generation,
+			decoded: Arc::new(DecodedLimit::default()),
 			//requested_images: 0,
+		};
+		loader.resize_workers(threads as usize)?;
+		Ok(loader)
+	}
+
+	/// Starts or stops threads so that `threads` of them are running, but at least one. The
+	/// stopped threads finish the request they are processing in the background before they
+	/// exit, the rest of their requests are processed by the other threads.
+	///
+	/// Returns an error if a thread can't be started. The same happens if the priority of
+	/// a new thread can't be lowered, but in that case the threads are all started and the
+	/// ones affected run at the normal priority.
+	pub fn set_thread_count(&mut self, threads: u32) -> Result<()> {
+		self.resize_workers(threads.max(1) as usize)
+	}
+
+	fn resize_workers(&mut self, threads: usize) -> Result<()> {
+		let (finished, stopped) = self
+			.stopped
+			.drain(..)
+			.partition(|handle: &thread::JoinHandle<()>| handle.is_finished());
+		self.stopped = stopped;
+		finished.into_iter().for_each(join_worker);
+		while self.workers.len() > threads {
+			// The last threads are the ones that were started most recently
+			let handle = self.workers.pop().unwrap().stop(&self.request_queue);
+			self.stopped.push(handle);
+		}
+		let mut priority_error = None;
+		while self.workers.len() < threads {
+			let running = self.running.clone();
+			let request_queue = self.request_queue.clone();
+			let loaded_img_tx = self.loaded_img_tx.clone();
+			let settings = self.settings.clone();
+			let decoder = self.decoder.clone();
+			let generation = self.generation.clone();
+			let decoded = self.decoded.clone();
+			let stopping = Arc::new(AtomicBool::new(false));
+
+			let thread_priority = self.thread_priority;
+
+			let thread_stopping = stopping.clone();
+			let (started_tx, started_rx) = sync_channel(1);
+			let handle = thread::Builder::new()
+				.name("image loader".into())
+				.spawn(move || {
+					let lowered = match thread_priority {
+						ThreadPriority::Low => lower_current_thread_priority(),
+						ThreadPriority::Normal => Ok(()),
+					};
+					let _ = started_tx.send(lowered);
+					Self::thread_loop(
+						running,
+						request_queue,
+						loaded_img_tx,
+						settings,
+						decoder,
+						generation,
+						decoded,
+						thread_stopping,
+					);
+				})
+				.chain_err(|| "Failed to start a loader thread")?;
+			self.workers.push(WorkerThread { stopping, handle });
+			if let Ok(Err(err)) = started_rx.recv() {
+				priority_error = priority_error.or(Some(err));
+			}
+		}
+		match priority_error {
+			Some(err) => {
+				Err(Error::with_chain(err, "Failed to lower the priority of a loader thread"))
+			}
+			None => Ok(()),
 		}
 	}
 
+	pub fn thread_count(&self) -> u32 {
+		self.workers.len() as u32
+	}
+
+	#[allow(clippy::too_many_arguments)]
 	fn thread_loop(
 		running: Arc<AtomicBool>,
-		load_request_rx: Arc<Mutex<Receiver<PathBuf>>>,
+		request_queue: Arc<RequestQueue>,
 		loaded_img_tx: Sender<LoadResult>,
+		settings: Arc<Mutex<LoadSettings>>,
+		decoder: Arc<Mutex<Arc<dyn Decoder>>>,
+		generation: Arc<AtomicUsize>,
+		decoded: Arc<DecodedLimit>,
+		stopping: Arc<AtomicBool>,
 	) {
 		// walk the directory starting from the current item and cache in all the images
 		// do this by stepping in both directions so that the cached images ahead of the file
 		// should never be more than 1 + "cached images before the file"
-		while running.load(Ordering::Acquire) {
-			let img_path = {
-				let load_request = load_request_rx.lock().unwrap();
-				load_request.recv().unwrap()
-			};
-			// It is very important that we release the mutex before starting to load the image
+		let local = Worker::new_fifo();
+		request_queue.add_thread(&local);
+		let keep_running = || running.load(Ordering::Acquire) && !stopping.load(Ordering::Acquire);
+		while let Some(request) = request_queue.pop(&local, keep_running) {
+			let img_path = request.path;
 
+			let settings = lock_settings(&settings).clone();
+			let decoder = decoder.lock().unwrap_or_else(PoisonError::into_inner).clone();
+			let cancelled = request.generation != generation.load(Ordering::Acquire);
+			if !cancelled {
+				decoded.reserve(keep_running);
+			}
+			let generation = request.generation;
 			let result = {
-				if let Ok(metadata) = fs::metadata(img_path.as_path()) {
-					if let Ok(image) = load_image(img_path.as_path()) {
-						LoadResult::Ok { path: img_path, metadata, image }
-					} else {
-						LoadResult::Failed
-					}
+				if cancelled {
+					LoadResult::Cancelled { path: img_path, generation }
 				} else {
-					LoadResult::Failed
+					match retry_io(settings.retry, || Ok(archive::metadata(img_path.as_path())?)) {
+						Ok(metadata) => {
+							// A decoder that panics on a malformed file must not take down the
+							// thread, the file is simply reported as one that couldn't be loaded.
+							let start = Instant::now();
+							let image = panic::catch_unwind(AssertUnwindSafe(|| {
+								retry_io(settings.retry, || {
+									decoder.decode(img_path.as_path(), &settings)
+								})
+							}));
+							match image {
+								Ok(Ok((image, info))) => LoadResult::Ok {
+									content_hash: loaded_content_hash(&img_path, &settings),
+									path: img_path,
+									metadata,
+									image,
+									info,
+									generation,
+									decode_time: start.elapsed(),
+								},
+								Ok(Err(error)) => {
+									LoadResult::Failed { path: img_path, error, generation }
+								}
+								Err(_) => LoadResult::Failed {
+									path: img_path,
+									error: "The decoder panicked".into(),
+									generation,
+								},
+							}
+						}
+						Err(error) => LoadResult::Failed { path: img_path, error, generation },
+					}
 				}
 			};
 
-			loaded_img_tx.send(result).unwrap();
+			// Only the decoded images take up room until they are received
+			if !cancelled && !matches!(result, LoadResult::Ok { .. }) {
+				decoded.release();
+			}
+			// The loader is gone, nobody is waiting for the rest of the requests
+			if loaded_img_tx.send(result).is_err() {
+				break;
+			}
 		}
+		request_queue.remove_thread(local);
+	}
+
+	pub fn settings(&self) -> LoadSettings {
+		lock_settings(&self.settings).clone()
+	}
+
+	pub fn decoder(&self) -> Arc<dyn Decoder> {
+		self.decoder.lock().unwrap_or_else(PoisonError::into_inner).clone()
+	}
+
+	/// The new decoder is only used for the requests that are processed after this call
+	pub fn set_decoder(&mut self, decoder: Arc<dyn Decoder>) {
+		*self.decoder.lock().unwrap_or_else(PoisonError::into_inner) = decoder;
+	}
+
+	/// The new settings only affect the requests that are processed after this call
+	pub fn set_settings(&mut self, settings: LoadSettings) {
+		*lock_settings(&self.settings) = settings;
 	}
 
+	/// Reports `Disconnected` once the received results run out if every thread has exited,
+	/// for example because they all panicked
 	pub fn try_recv_prefetched(&mut self) -> std::result::Result<LoadResult, TryRecvError> {
-		self.image_rx.try_recv()
+		match self.image_rx.try_recv() {
+			Err(TryRecvError::Empty)
+				if self.workers.iter().all(|worker| worker.handle.is_finished()) =>
+			{
+				Err(TryRecvError::Disconnected)
+			}
+			Ok(result @ LoadResult::Ok { .. }) => {
+				self.decoded.release();
+				Ok(result)
+			}
+			result => result,
+		}
+	}
+
+	/// Same as `try_recv_prefetched` but waits up to `timeout` for the next result
+	pub fn recv_prefetched_timeout(
+		&mut self,
+		timeout: Duration,
+	) -> std::result::Result<LoadResult, RecvTimeoutError> {
+		if self.workers.iter().all(|worker| worker.handle.is_finished()) {
+			return self.try_recv_prefetched().map_err(|err| match err {
+				TryRecvError::Empty => RecvTimeoutError::Timeout,
+				TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+			});
+		}
+		match self.image_rx.recv_timeout(timeout) {
+			Ok(result @ LoadResult::Ok { .. }) => {
+				self.decoded.release();
+				Ok(result)
+			}
+			result => result,
+		}
+	}
+
+	/// Limits the number of images that the threads hold decoded while waiting for them to be
+	/// received. Once the limit is reached the threads wait before decoding the next image.
+	/// `None` means no limit.
+	pub fn set_max_decoded(&mut self, limit: Option<usize>) {
+		self.decoded.set_limit(limit);
+	}
+
+	/// Sends the request to the first thread that is free to process it.
+	///
+	/// Returns false if the request was not sent because the queue of the threads is full
+	/// or there are no threads.
+	pub fn send_load_request(&mut self, path: PathBuf) -> bool {
+		let limit = self.workers.len() * Self::REQUEST_QUEUE_DEPTH;
+		if self.request_queue.queued.load(Ordering::Acquire) >= limit {
+			return false;
+		}
+		let generation = self.generation();
+		self.request_queue.push(LoadRequest { path, generation });
+		true
+	}
+
+	pub fn generation(&self) -> usize {
+		self.generation.load(Ordering::Acquire)
 	}
 
-	pub fn send_load_request(&mut self, path: PathBuf) {
-		self.path_tx.send(path).unwrap();
+	/// Makes the threads skip all the requests that were sent before this call. A result is
+	/// still returned for every request, skipped ones are reported as `LoadResult::Cancelled`.
+	pub fn cancel_pending_requests(&mut self) {
+		self.generation.fetch_add(1, Ordering::AcqRel);
 	}
 }
 
+/// Locks the settings even if a thread panicked while holding the lock. The settings are only
+/// ever replaced as a whole so they can't be left in an inconsistent state.
+fn lock_settings(settings: &Mutex<LoadSettings>) -> MutexGuard<'_, LoadSettings> {
+	settings.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 impl Drop for ImageLoader {
 	fn drop(&mut self) {
+		// The threads exit without processing the rest of their requests
 		self.running.store(false, Ordering::Release);
+		self.decoded.set_limit(None);
+		// The queue is closed before joining any of the threads so that they exit at the
+		// same time
+		self.request_queue.close();
+		for worker in self.workers.drain(..) {
+			join_worker(worker.handle);
+		}
+		self.stopped.drain(..).for_each(join_worker);
+	}
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+	use super::*;
+
+	/// A 2 by 1 image with a red and a green pixel
+	fn two_pixels() -> image::RgbaImage {
+		let mut image = image::RgbaImage::new(2, 1);
+		image.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+		image.put_pixel(1, 0, image::Rgba([0, 255, 0, 255]));
+		image
+	}
+
+	#[test]
+	fn orientation_1_keeps_the_image() {
+		assert_eq!(apply_orientation(two_pixels(), 1), two_pixels());
+	}
+
+	#[test]
+	fn orientation_2_mirrors_the_image() {
+		let image = apply_orientation(two_pixels(), 2);
+		assert_eq!(image.dimensions(), (2, 1));
+		assert_eq!(image.get_pixel(0, 0)[1], 255);
+	}
+
+	#[test]
+	fn orientation_6_rotates_clockwise() {
+		let image = apply_orientation(two_pixels(), 6);
+		assert_eq!(image.dimensions(), (1, 2));
+		assert_eq!(image.get_pixel(0, 0)[0], 255);
+		assert_eq!(image.get_pixel(0, 1)[1], 255);
+	}
+
+	#[test]
+	fn orientation_8_rotates_counterclockwise() {
+		let image = apply_orientation(two_pixels(), 8);
+		assert_eq!(image.dimensions(), (1, 2));
+		assert_eq!(image.get_pixel(0, 0)[1], 255);
+		assert_eq!(image.get_pixel(0, 1)[0], 255);
+	}
 
-		if let Some(join_handles) = self.join_handles.take() {
-			for _ in join_handles.iter() {
-				self.path_tx.send(PathBuf::from("")).unwrap();
+	#[test]
+	fn files_without_exif_are_not_rotated() {
+		assert_eq!(get_orientation(b"not an image"), 1);
+	}
+
+	/// Writes a GIF with `frame_count` frames into the temporary directory
+	fn write_gif(name: &str, frame_count: usize) -> PathBuf {
+		use image::codecs::gif::GifEncoder;
+
+		let mut bytes = Vec::new();
+		let frames = (0..frame_count).map(|_| image::Frame::new(two_pixels()));
+		GifEncoder::new(&mut bytes).encode_frames(frames).unwrap();
+		let path = std::env::temp_dir().join(format!("emulsion-test-{}.gif", name));
+		fs::write(&path, bytes).unwrap();
+		path
+	}
+
+	#[test]
+	fn still_gifs_are_decoded_from_their_only_frame() {
+		let path = write_gif("still", 1);
+		let (image, _) = load_image(&path, &LoadSettings::default()).unwrap();
+		fs::remove_file(&path).unwrap();
+		match image {
+			DecodedImage::Still(image) => assert_eq!(image.dimensions(), (2, 1)),
+			_ => panic!("The GIF wasn't decoded as a still image"),
+		}
+	}
+
+	#[test]
+	fn animated_gifs_keep_every_frame() {
+		let path = write_gif("animated", 3);
+		let (image, _) = load_image(&path, &LoadSettings::default()).unwrap();
+		fs::remove_file(&path).unwrap();
+		match image {
+			DecodedImage::Animation(frames) => assert_eq!(frames.len(), 3),
+			_ => panic!("The GIF wasn't decoded as an animation"),
+		}
+	}
+
+	#[test]
+	fn regular_pngs_are_decoded_as_still_images() {
+		let path = std::env::temp_dir().join("emulsion-test-still.png");
+		two_pixels().save(&path).unwrap();
+		let (image, _) = load_image(&path, &LoadSettings::default()).unwrap();
+		fs::remove_file(&path).unwrap();
+		match image {
+			DecodedImage::Still(image) => assert_eq!(image.dimensions(), (2, 1)),
+			_ => panic!("The PNG wasn't decoded as a still image"),
+		}
+	}
+
+	/// Encodes the 2 by 1 image as a lossless WebP
+	fn encode_webp() -> Vec<u8> {
+		let mut bytes = Vec::new();
+		let encoder = image_webp::WebPEncoder::new(&mut bytes);
+		encoder.encode(two_pixels().as_raw(), 2, 1, image_webp::ColorType::Rgba8).unwrap();
+		bytes
+	}
+
+	/// Builds an animated WebP that shows the 2 by 1 image `frame_count` times
+	fn animated_webp(frame_count: usize) -> Vec<u8> {
+		fn chunk(name: &[u8], data: &[u8]) -> Vec<u8> {
+			let mut chunk = name.to_vec();
+			chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+			chunk.extend_from_slice(data);
+			if data.len() % 2 == 1 {
+				chunk.push(0);
 			}
+			chunk
+		}
+		// The still image is a RIFF header followed by the VP8L chunk
+		let frame_data = &encode_webp()[12..];
+		// Animated with alpha, the canvas is 2 by 1
+		let mut contents = b"WEBP".to_vec();
+		contents.extend(chunk(b"VP8X", &[0x12, 0, 0, 0, 1, 0, 0, 0, 0, 0]));
+		contents.extend(chunk(b"ANIM", &[0; 6]));
+		for _ in 0..frame_count {
+			// At the origin, 2 by 1 pixels, shown for 100 ms
+			let mut frame = vec![0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 100, 0, 0, 0];
+			frame.extend_from_slice(frame_data);
+			contents.extend(chunk(b"ANMF", &frame));
+		}
+		chunk(b"RIFF", &contents)
+	}
 
-			for handle in join_handles.into_iter() {
-				if let Err(err) = handle.join() {
-					eprintln!("Error occured while joining handle {:?}", err);
-				}
+	#[test]
+	fn still_webps_have_no_frames() {
+		let (frames, _) = decode_webp_frames(encode_webp().as_slice()).unwrap();
+		assert!(frames.is_empty());
+	}
+
+	#[test]
+	fn animated_webps_keep_every_frame() {
+		let (frames, _) = decode_webp_frames(animated_webp(2).as_slice()).unwrap();
+		assert_eq!(frames.len(), 2);
+		assert_eq!(frames[1].buffer().dimensions(), (2, 1));
+		// The frames are blended onto the canvas, which may round the colors slightly
+		assert!(frames[1].buffer().get_pixel(1, 0)[1] >= 254);
+		assert_eq!(Duration::from(frames[1].delay()), Duration::from_millis(100));
+	}
+
+	#[test]
+	fn mipmap_levels_stop_at_a_single_texel() {
+		assert_eq!(get_mipmap_levels((1, 1)), 0);
+		assert_eq!(get_mipmap_levels((1, 512)), 0);
+		assert_eq!(get_mipmap_levels((2, 2)), 1);
+		assert_eq!(get_mipmap_levels((8, 3)), 3);
+	}
+
+	#[test]
+	fn mipmap_levels_are_limited() {
+		assert_eq!(get_mipmap_levels((4096, 4096)), MAX_MIPMAP_LEVELS);
+	}
+
+	#[test]
+	fn size_estimate_includes_the_mipmaps() {
+		assert_eq!(get_image_size_estimate((1, 1)), 4);
+		// 4x4 + 2x2 + 1x1 texels
+		assert_eq!(get_image_size_estimate((4, 4)), 21 * 4);
+	}
+
+	fn still_pixels(image: DecodedImage) -> image::RgbaImage {
+		match image {
+			DecodedImage::Still(image) => image,
+			_ => panic!("Expected a still image"),
+		}
+	}
+
+	#[test]
+	fn rotations_turn_clockwise() {
+		let image = image::RgbaImage::from_fn(2, 1, |x, _| image::Rgba([x as u8, 0, 0, 255]));
+		let rotated = still_pixels(DecodedImage::Still(image.clone()).rotated(90));
+		assert_eq!(rotated.dimensions(), (1, 2));
+		assert_eq!(rotated.get_pixel(0, 0).0[0], 0);
+		assert_eq!(rotated.get_pixel(0, 1).0[0], 1);
+		let rotated = still_pixels(DecodedImage::Still(image.clone()).rotated(180));
+		assert_eq!(rotated.get_pixel(0, 0).0[0], 1);
+		let rotated = still_pixels(DecodedImage::Still(image.clone()).rotated(270));
+		assert_eq!(rotated.get_pixel(0, 0).0[0], 1);
+		assert_eq!(rotated.get_pixel(0, 1).0[0], 0);
+	}
+
+	#[test]
+	fn rotations_add_up() {
+		let image = image::RgbaImage::from_fn(3, 2, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+		let turned = DecodedImage::Still(image.clone()).rotated(90).rotated(180);
+		assert_eq!(still_pixels(turned), still_pixels(DecodedImage::Still(image).rotated(270)));
+	}
+
+	fn retried(policy: RetryPolicy, failures: u32, error: fn() -> Error) -> (Result<()>, u32) {
+		let mut attempts = 0;
+		let result = retry_io(policy, || {
+			attempts += 1;
+			if attempts <= failures {
+				Err(error())
+			} else {
+				Ok(())
 			}
+		});
+		(result, attempts)
+	}
+
+	#[test]
+	fn io_errors_are_retried() {
+		let policy = RetryPolicy { retries: 2, initial_delay: Duration::from_millis(1) };
+		let io_error = || std::io::Error::other("unavailable").into();
+		let (result, attempts) = retried(policy, 2, io_error);
+		assert!(result.is_ok());
+		assert_eq!(attempts, 3);
+		let (result, attempts) = retried(policy, 5, io_error);
+		assert!(result.is_err());
+		assert_eq!(attempts, 3);
+	}
+
+	#[test]
+	fn other_errors_are_not_retried() {
+		let policy = RetryPolicy { retries: 2, initial_delay: Duration::from_millis(1) };
+		let (result, attempts) = retried(policy, 1, || "not an image".into());
+		assert!(result.is_err());
+		assert_eq!(attempts, 1);
+	}
+
+	#[test]
+	fn linear_values_are_encoded_as_srgb() {
+		let mut image = image::RgbaImage::from_pixel(1, 1, image::Rgba([0, 128, 255, 128]));
+		encode_linear_as_srgb(&mut image);
+		// The alpha stays linear
+		assert_eq!(image.get_pixel(0, 0).0, [0, 188, 255, 128]);
+	}
+
+	#[test]
+	fn srgb_values_are_decoded_to_linear() {
+		assert_eq!(srgb_to_linear(0.0), 0.0);
+		assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+		assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+		// The linear segment at the dark end
+		assert!((srgb_to_linear(0.04) - 0.04 / 12.92).abs() < 1e-6);
+		for step in 0..=10 {
+			let value = step as f32 / 10.0;
+			assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-5);
+		}
+	}
+
+	#[test]
+	fn colors_are_multiplied_by_their_alpha() {
+		let pixels = [[200, 100, 50, 128], [255, 255, 255, 255], [255, 255, 255, 0]];
+		let mut image = image::RgbaImage::from_fn(3, 1, |x, _| image::Rgba(pixels[x as usize]));
+		premultiply_alpha(&mut image, false);
+		assert_eq!(image.get_pixel(0, 0).0, [100, 50, 25, 128]);
+		assert_eq!(image.get_pixel(1, 0).0, [255, 255, 255, 255]);
+		assert_eq!(image.get_pixel(2, 0).0, [0, 0, 0, 0]);
+	}
+
+	#[test]
+	fn srgb_colors_are_multiplied_in_linear_space() {
+		let mut image = image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 128, 0, 128]));
+		premultiply_alpha(&mut image, true);
+		assert_eq!(image.get_pixel(0, 0).0, [188, 93, 0, 128]);
+	}
+
+	#[test]
+	fn xmp_orientations_are_read_from_attributes_and_elements() {
+		let attribute = br#"<x:xmpmeta><rdf:Description tiff:Orientation="6"/></x:xmpmeta>"#;
+		assert_eq!(get_xmp_orientation(attribute), Some(6));
+		let element = b"<x:xmpmeta><tiff:Orientation>8</tiff:Orientation></x:xmpmeta>";
+		assert_eq!(get_xmp_orientation(element), Some(8));
+		assert_eq!(get_orientation(element), 8);
+	}
+
+	#[test]
+	fn invalid_xmp_orientations_are_ignored() {
+		let out_of_range = br#"<x:xmpmeta tiff:Orientation="9"></x:xmpmeta>"#;
+		assert_eq!(get_xmp_orientation(out_of_range), None);
+		// The property only counts inside the packet
+		let outside = br#"<x:xmpmeta></x:xmpmeta> tiff:Orientation="3""#;
+		assert_eq!(get_xmp_orientation(outside), None);
+		assert_eq!(get_xmp_orientation(b"tiff:Orientation=\"3\""), None);
+		assert_eq!(get_orientation(b"no metadata"), 1);
+	}
+
+	#[test]
+	fn squares_are_cropped_from_the_center() {
+		let image = image::RgbaImage::from_fn(4, 2, |x, _| image::Rgba([x as u8, 0, 0, 255]));
+		let square = crop_to_square(image, 2);
+		assert_eq!(square.dimensions(), (2, 2));
+		assert_eq!(square.get_pixel(0, 0).0[0], 1);
+		assert_eq!(square.get_pixel(1, 1).0[0], 2);
+	}
+
+	#[test]
+	fn cropped_squares_are_scaled_to_the_size() {
+		let image = image::RgbaImage::new(30, 90);
+		assert_eq!(crop_to_square(image, 10).dimensions(), (10, 10));
+	}
+
+	#[test]
+	fn fitted_dimensions_are_rounded() {
+		assert_eq!(fitted_dimensions((1000, 333), 500), (500, 167));
+		assert_eq!(fitted_dimensions((3000, 1), 300), (300, 1));
+		assert_eq!(fitted_dimensions((200, 100), 500), (200, 100));
+	}
+
+	#[test]
+	fn jpegs_are_decoded_at_the_smallest_scale_that_fits() {
+		assert_eq!(scaled_jpeg_dimensions((5472, 3648), 684), (684, 456));
+		assert_eq!(scaled_jpeg_dimensions((5472, 3648), 1000), (1368, 912));
+		assert_eq!(scaled_jpeg_dimensions((5472, 3648), 1400), (2736, 1824));
+		assert_eq!(scaled_jpeg_dimensions((5472, 3648), 4000), (5472, 3648));
+		assert_eq!(scaled_jpeg_dimensions((500, 333), 600), (500, 333));
+		assert_eq!(scaled_jpeg_dimensions((500, 333), 63), (63, 42));
+	}
+
+	#[test]
+	fn fitting_matches_the_fitted_dimensions() {
+		let image = image::RgbaImage::new(999, 333);
+		let fitted = fit_to_dimension(image, 500);
+		assert_eq!(fitted.dimensions(), fitted_dimensions((999, 333), 500));
+	}
+
+	#[test]
+	fn idle_threads_take_the_requests_of_the_others() {
+		let queue = RequestQueue::default();
+		let busy = Worker::new_fifo();
+		let idle = Worker::new_fifo();
+		queue.add_thread(&busy);
+		for name in &["a", "b"] {
+			queue.push(LoadRequest { path: PathBuf::from(name), generation: 0 });
+		}
+		// The busy thread has taken both requests into its own queue
+		while !queue.injector.is_empty() {
+			let _ = queue.injector.steal_batch(&busy);
+		}
+
+		let stolen = queue.pop(&idle, || true).unwrap();
+		assert_eq!(stolen.path, Path::new("a"));
+		assert_eq!(queue.queued.load(Ordering::Acquire), 1);
+		assert_eq!(queue.pop(&busy, || true).unwrap().path, Path::new("b"));
+	}
+
+	#[test]
+	fn at_least_one_thread_is_kept() {
+		let mut loader = ImageLoader::new(3, ThreadPriority::Normal).unwrap();
+		loader.set_thread_count(0).unwrap();
+		assert_eq!(loader.thread_count(), 1);
+		loader.set_thread_count(2).unwrap();
+		assert_eq!(loader.thread_count(), 2);
+	}
+
+	#[test]
+	fn stopped_threads_are_joined() {
+		let mut loader = ImageLoader::new(3, ThreadPriority::Normal).unwrap();
+		loader.set_thread_count(1).unwrap();
+		assert_eq!(loader.stopped.len(), 2);
+		while !loader.stopped.iter().all(|handle| handle.is_finished()) {
+			thread::sleep(Duration::from_millis(1));
+		}
+		loader.set_thread_count(1).unwrap();
+		assert!(loader.stopped.is_empty());
+	}
+
+	/// Returns a document with a 4 by 2 and a 2 by 2 page for every file, without reading it
+	pub(in crate::image_cache) struct StubDecoder;
+
+	impl Decoder for StubDecoder {
+		fn decode(
+			&self,
+			_image_path: &Path,
+			settings: &LoadSettings,
+		) -> Result<(DecodedImage, Option<ImageInfo>)> {
+			let page = |width| apply_settings(image::RgbaImage::new(width, 2), settings);
+			Ok((DecodedImage::Pages(vec![page(4), page(2)]), None))
+		}
+
+		fn dimensions(&self, _image_path: &Path) -> Result<(u32, u32)> {
+			Ok((4, 2))
+		}
+	}
+
+	/// Keeps the dimensions of the images instead of creating textures
+	pub(in crate::image_cache) struct StubUploader;
+
+	impl TextureSize for (u32, u32) {
+		fn texture_dimensions(&self) -> (u32, u32) {
+			*self
+		}
+	}
+
+	impl Uploader for StubUploader {
+		type Texture = (u32, u32);
+
+		fn upload(&self, image: image::RgbaImage) -> Result<(u32, u32)> {
+			Ok(image.dimensions())
+		}
+
+		#[cfg(feature = "compressed")]
+		fn upload_compressed(&self, image: &CompressedImage) -> Result<(u32, u32)> {
+			Ok((image.width, image.height))
 		}
 	}
+
+	#[test]
+	fn decoded_files_are_uploaded_by_the_uploader() {
+		let mut loader = ImageLoader::new(1, ThreadPriority::Normal).unwrap();
+		loader.set_decoder(Arc::new(StubDecoder));
+		// The metadata of the file is read before it's decoded, so it has to exist
+		let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+		assert!(loader.send_load_request(path.clone()));
+		let (metadata, image) = match loader.recv_prefetched_timeout(Duration::from_secs(10)) {
+			Ok(LoadResult::Ok { path: loaded_path, metadata, image, .. }) => {
+				assert_eq!(loaded_path, path);
+				(metadata, image)
+			}
+			_ => panic!("The file was not decoded"),
+		};
+		let cached = CachedTexture::from_decoded(&StubUploader, metadata, image, false).unwrap();
+		assert_eq!(cached.page_count(), 2);
+		assert_eq!(cached.page(1).as_deref(), Some(&(2, 2)));
+		let size_estimate = get_image_size_estimate((4, 2)) + get_image_size_estimate((2, 2));
+		assert_eq!(cached.size_estimate(), size_estimate as isize);
+	}
+
+	#[test]
+	fn thumbnails_are_made_by_the_decoder() {
+		let settings = LoadSettings::default();
+		let thumbnail = StubDecoder.thumbnail(Path::new("stub"), 2, &settings).unwrap();
+		assert_eq!(thumbnail.dimensions(), (2, 1));
+		let square = StubDecoder.square_thumbnail(Path::new("stub"), 2, &settings).unwrap();
+		assert_eq!(square.dimensions(), (2, 2));
+	}
+
+	#[cfg(feature = "compressed")]
+	#[test]
+	fn compressed_textures_are_estimated_by_their_blocks() {
+		let metadata = fs::metadata(env!("CARGO_MANIFEST_DIR")).unwrap();
+		let image = CompressedImage {
+			width: 8,
+			height: 4,
+			format: CompressedSrgbFormat::S3tcDxt1Alpha,
+			data: vec![0; 16],
+		};
+		let image = DecodedImage::Compressed(image);
+		assert_eq!(image.size_estimate(), 16);
+		let cached = CachedTexture::from_decoded(&StubUploader, metadata, image, false).unwrap();
+		assert!(matches!(cached, CachedTexture::Compressed(_)));
+		assert_eq!(cached.size_estimate(), 16);
+	}
 }
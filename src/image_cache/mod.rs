@@ -1,22 +1,40 @@
-use std::collections::BTreeMap;
+use std::any::Any;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::{OsStr, OsString};
 use std::fs;
+use std::io;
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use gelatin::glium;
+use gelatin::image;
 
 use glium::texture::SrgbTexture2d;
 
+mod archive;
+#[cfg(feature = "compressed")]
+mod compressed;
+mod dir_watcher;
 mod image_loader;
+use self::dir_watcher::DirWatcher;
 use self::image_loader::*;
+pub use self::image_loader::{
+	DecodedImage, Decoder, ImageInfo, ImageTexture, LoadSettings, SamplingHint, Uploader,
+};
 
 pub mod errors {
 	use crate::image_cache::image_loader;
 	use gelatin::glium::texture;
 	use gelatin::image;
 	use std::io;
+	use std::path::PathBuf;
 
 	error_chain! {
 		foreign_links {
@@ -24,6 +42,45 @@ pub mod errors {
 			TextureCreationError(texture::TextureCreationError);
 			ImageLoadError(image::ImageError);
 			TextureLoaderError(image_loader::errors::Error);
+			WatcherError(notify::Error);
+		}
+
+		errors {
+			EmptyDirectory {
+				description("there are no supported images in the directory")
+				display("There are no supported images in the directory")
+			}
+			/// The file could be read but its contents are not a valid image, for example
+			/// because it's empty or truncated
+			CorruptImage(path: PathBuf) {
+				description("the file is not a valid image")
+				display("The file '{}' is not a valid image", path.display())
+			}
+			/// Every loader thread has stopped, so images are no longer loaded in the background
+			LoaderDisconnected {
+				description("the image loader threads have stopped")
+				display("The image loader threads have stopped")
+			}
+			/// Navigation reached the first or the last file while wrapping around is disabled
+			EndOfDirectory {
+				description("there are no more files in this direction")
+				display("There are no more files in this direction")
+			}
+			/// None of the files in the directory satisfy the condition of `load_matching`
+			NoMatchingFile {
+				description("no file in the directory matches")
+				display("No file in the directory matches")
+			}
+			/// The loader threads have as many requests waiting as they can hold
+			QueueFull {
+				description("the queues of the loader threads are full")
+				display("The queues of the loader threads are full")
+			}
+			/// The image wasn't decoded within the time given to `load_specific_timeout`
+			Timeout {
+				description("loading the image timed out")
+				display("Loading the image timed out")
+			}
 		}
 	}
 }
@@ -31,28 +88,462 @@ pub mod errors {
 pub use self::errors::Result;
 use self::errors::*;
 
+/// Reports the errors of the image decoder as `CorruptImage` if `report_corrupt` is true, so
+/// that they can be told apart from failing to read the file
+fn classify_load_error(
+	error: image_loader::errors::Error,
+	path: &Path,
+	report_corrupt: bool,
+) -> Error {
+	match error.kind() {
+		image_loader::errors::ErrorKind::ImageLoadError(_) if report_corrupt => {
+			Error::with_chain(error, ErrorKind::CorruptImage(path.to_owned()))
+		}
+		_ => error.into(),
+	}
+}
+
+/// Tells if the file changed since the `cached` metadata was queried. Some file systems don't
+/// store the modification time, in that case only a different size counts as a change.
+fn is_modified(cached: &fs::Metadata, current: &fs::Metadata) -> bool {
+	match (cached.modified(), current.modified()) {
+		(Ok(cached), Ok(current)) => cached != current,
+		_ => cached.len() != current.len(),
+	}
+}
+
+/// Where a jump from a page of the current file ends up
+#[derive(Debug, PartialEq)]
+enum PageJump {
+	/// Steps this many pages within the current document
+	Page(isize),
+	/// Moves this many files, once the steps within the current document are used up
+	File(i32),
+}
+
+/// Splits a jump of `jump_count` steps from `current_page` of a file with `page_count` pages.
+/// Each page is a step, moving past the first or the last page takes one more step to get to
+/// the neighboring file.
+fn page_jump(current_page: usize, page_count: usize, jump_count: i32) -> PageJump {
+	let last_page = page_count.max(1) as i32 - 1;
+	let target_page = current_page as i32 + jump_count;
+	if jump_count == 0 {
+		PageJump::File(0)
+	} else if target_page > last_page {
+		PageJump::File(target_page - last_page)
+	} else if target_page < 0 {
+		PageJump::File(target_page)
+	} else {
+		PageJump::Page(jump_count as isize)
+	}
+}
+
+/// The rotation that keeps the image displayed as before, mirrored horizontally or vertically,
+/// once its flip is toggled. The images are flipped before they are rotated, and flipping
+/// after rotating by R is the same as rotating by -R after flipping. A vertical flip is a
+/// horizontal flip followed by a half turn.
+fn flipped_rotation(rotation: u32, horizontal: bool) -> u32 {
+	let rotation = rotation as i32;
+	let flipped = if horizontal { -rotation } else { -(rotation + 180) };
+	flipped.rem_euclid(360) as u32
+}
+
+/// Tells if the hash of the file differs from the one stored when the texture was loaded.
+/// This is always false for the textures that were loaded without content hashing.
+fn content_changed<T: TextureSize>(path: &Path, cached: &CachedTexture<T>) -> bool {
+	match cached.content_hash() {
+		Some(stored) => content_hash(path).is_ok_and(|current| current != stored),
+		None => false,
+	}
+}
+
+/// Tells if the entry was loaded from the file as it is now, `metadata` being the current
+/// metadata of the file. The entries that weren't loaded from a file are never current.
+fn is_current<T: TextureSize>(
+	path: &Path,
+	cached: &CachedTexture<T>,
+	metadata: &fs::Metadata,
+) -> bool {
+	match cached.metadata() {
+		Some(cached_metadata) => {
+			!is_modified(cached_metadata, metadata) && !content_changed(path, cached)
+		}
+		None => false,
+	}
+}
+
+/// Canonicalizes the path if possible, otherwise only makes it absolute. Canonicalizing fails
+/// for example when the target of a symlink is not available, but that's only an error if the
+/// file can't be loaded either.
+fn resolve_path(path: &Path) -> Result<PathBuf> {
+	match archive::canonicalize(path) {
+		Ok(path) => Ok(path),
+		Err(_) if path.is_absolute() => Ok(path.to_owned()),
+		Err(_) => Ok(std::env::current_dir()?.join(path)),
+	}
+}
+
+/// Tells if the file is hidden, which means that its name starts with a dot, or on Windows that
+/// it has the hidden attribute
+fn is_hidden(path: &Path) -> bool {
+	let dot_file = match path.file_name() {
+		Some(name) => name.to_string_lossy().starts_with('.'),
+		None => false,
+	};
+	dot_file || has_hidden_attribute(path)
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute(path: &Path) -> bool {
+	use std::os::windows::fs::MetadataExt;
+
+	const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+	match fs::metadata(path) {
+		Ok(metadata) => metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0,
+		Err(_) => false,
+	}
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_path: &Path) -> bool {
+	false
+}
+
 struct ImageDescriptor {
-	dir_entry: fs::DirEntry,
+	/// This points into the archive if the file is inside one
+	path: PathBuf,
+	/// The path of the file relative to the directory that was opened. This is the same as the
+	/// filename unless the subdirectories are also traversed.
+	name: OsString,
+	/// The key of the texture in the cache. This is the name of the file unless it's the same
+	/// file on the disk as another one that was loaded before it.
+	cache_key: OsString,
 	//frame_count: Option<u32>, // it is evaluated in an on-demand fashion
 }
 
 impl ImageDescriptor {
-	fn from_entry(dir_entry: fs::DirEntry) -> ImageDescriptor {
-		ImageDescriptor { dir_entry /* frame_count: None */ }
+	fn new(path: PathBuf, root: &Path) -> ImageDescriptor {
+		let name = match path.strip_prefix(root) {
+			Ok(relative) => relative.as_os_str().to_owned(),
+			Err(_) => path.file_name().unwrap_or_default().to_owned(),
+		};
+		ImageDescriptor { path, cache_key: name.clone(), name /* frame_count: None */ }
+	}
+
+	fn file_name(&self) -> &OsStr {
+		self.path.file_name().unwrap_or(self.name.as_os_str())
+	}
+
+	fn metadata(&self) -> io::Result<fs::Metadata> {
+		archive::metadata(self.path.as_path())
+	}
+}
+
+#[cfg(unix)]
+type FileIdentity = (u64, u64);
+#[cfg(not(unix))]
+type FileIdentity = String;
+
+/// Identifies the file on the disk so that hardlinks and paths that only differ in case on a
+/// case insensitive file system are recognized as the same file. The files inside archives
+/// have no identity.
+#[cfg(unix)]
+fn file_identity(path: &Path) -> Option<FileIdentity> {
+	use std::os::unix::fs::MetadataExt;
+
+	if archive::split_archive_path(path).is_some() {
+		return None;
+	}
+	let metadata = fs::metadata(path).ok()?;
+	Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_identity(path: &Path) -> Option<FileIdentity> {
+	if archive::split_archive_path(path).is_some() {
+		return None;
+	}
+	Some(path.canonicalize().ok()?.to_string_lossy().to_lowercase())
+}
+
+/// Determines which textures are removed from the cache when it gets full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EvictionPolicy {
+	/// Keep the textures of the files closest to the current file in the directory
+	Positional,
+	/// Keep the textures that were accessed most recently
+	LeastRecentlyUsed,
+}
+
+/// The shape of a thumbnail
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThumbnailMode {
+	/// Keep the aspect ratio of the image and fit it in the square
+	Fit,
+	/// Crop the center of the image so that the thumbnail fills the whole square
+	SquareCrop,
+}
+
+/// Thumbnails are keyed by the canonical path of the file, their size and their mode
+type ThumbnailKey = (PathBuf, u32, ThumbnailMode);
+
+/// The order in which the files of a directory are navigated
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SortOrder {
+	/// Compares the numbers within filenames by their numeric value, so "img2" comes before "img10"
+	Natural,
+	/// Compares the filenames character by character
+	Lexicographic,
+	ModificationTime,
+	Size,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SortDirection {
+	Ascending,
+	Descending,
+}
+
+impl SortDirection {
+	fn apply(self, ordering: Ordering) -> Ordering {
+		match self {
+			SortDirection::Ascending => ordering,
+			SortDirection::Descending => ordering.reverse(),
+		}
+	}
+}
+
+/// Where the texture returned by a load method came from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoadSource {
+	/// The texture was already in the cache, for example because it was prefetched
+	Cache,
+	/// The file was decoded by the load method
+	Decoded,
+}
+
+/// The amount of memory the cached textures may take up
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CacheCapacity {
+	Bytes(isize),
+	/// A fraction (between 0 and 1) of the total video memory. The free memory isn't used,
+	/// because it depends on what else happens to be running when the cache is created.
+	VideoMemoryFraction(f64),
+}
+
+impl CacheCapacity {
+	/// Used in place of the total video memory if the driver doesn't report it
+	const FALLBACK_VIDEO_MEMORY: isize = 500_000_000;
+
+	/// Returns the capacity in bytes
+	pub fn resolve(self, display: &glium::Display) -> isize {
+		match self {
+			CacheCapacity::Bytes(bytes) => bytes,
+			CacheCapacity::VideoMemoryFraction(fraction) => {
+				let video_memory =
+					get_total_video_memory(display).unwrap_or(Self::FALLBACK_VIDEO_MEMORY);
+				(video_memory as f64 * fraction.clamp(0.0, 1.0)) as isize
+			}
+		}
+	}
+}
+
+/// Returns the total amount of video memory in bytes. Only the NVIDIA drivers report it,
+/// glium doesn't query it so it's read with `glGetIntegerv` directly.
+fn get_total_video_memory(display: &glium::Display) -> Option<isize> {
+	use glium::backend::Facade;
+	use glium::CapabilitiesSource;
+	use std::os::raw::c_void;
+
+	const GPU_MEMORY_INFO_TOTAL_AVAILABLE_MEMORY_NVX: u32 = 0x9048;
+	type GetIntegerv = extern "system" fn(u32, *mut i32);
+
+	let context = display.get_context();
+	if !context.get_extensions().gl_nvx_gpu_memory_info {
+		return None;
+	}
+	let kilobytes = unsafe {
+		context.exec_in_context(|| {
+			let get_integerv = display.gl_window().get_proc_address("glGetIntegerv");
+			if get_integerv.is_null() {
+				return None;
+			}
+			let get_integerv: GetIntegerv = mem::transmute(get_integerv as *const c_void);
+			let mut kilobytes = 0;
+			get_integerv(GPU_MEMORY_INFO_TOTAL_AVAILABLE_MEMORY_NVX, &mut kilobytes);
+			Some(kilobytes)
+		})
+	};
+	kilobytes.map(|kilobytes| kilobytes as isize * 1024)
+}
+
+impl FromStr for CacheCapacity {
+	type Err = Error;
+
+	/// Accepts a number of bytes ("1000000"), a size with a unit ("512MiB", "2 GB") or
+	/// a percentage of the video memory ("25%"). Units are case insensitive.
+	fn from_str(spec: &str) -> Result<CacheCapacity> {
+		let spec = spec.trim();
+		if let Some(percentage) = spec.strip_suffix('%') {
+			let percentage: f64 = percentage
+				.trim()
+				.parse()
+				.map_err(|_| format!("Invalid percentage in cache capacity '{}'", spec))?;
+			return Ok(CacheCapacity::VideoMemoryFraction(percentage / 100.0));
+		}
+
+		let unit_start = spec.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(spec.len());
+		let (number, unit) = spec.split_at(unit_start);
+		let number: f64 =
+			number.parse().map_err(|_| format!("Invalid number in cache capacity '{}'", spec))?;
+		let multiplier: f64 = match unit.trim().to_ascii_lowercase().as_str() {
+			"" | "b" => 1.0,
+			"kb" => 1e3,
+			"mb" => 1e6,
+			"gb" => 1e9,
+			"kib" => 1024.0,
+			"mib" => 1024.0 * 1024.0,
+			"gib" => 1024.0 * 1024.0 * 1024.0,
+			_ => bail!(format!("Unknown unit in cache capacity '{}'", spec)),
+		};
+		Ok(CacheCapacity::Bytes((number * multiplier) as isize))
+	}
+}
+
+/// The number, the total and the longest duration of the measured operations
+#[derive(Debug, Clone, Default)]
+pub struct TimingStats {
+	pub count: u32,
+	pub total: Duration,
+	pub max: Duration,
+	/// The file that took the longest
+	pub slowest: Option<PathBuf>,
+}
+
+impl TimingStats {
+	fn record(&mut self, duration: Duration, path: &Path) {
+		self.count += 1;
+		self.total += duration;
+		if self.slowest.is_none() || duration > self.max {
+			self.max = duration;
+			self.slowest = Some(path.to_owned());
+		}
+	}
+
+	pub fn average(&self) -> Duration {
+		if self.count == 0 {
+			Duration::default()
+		} else {
+			self.total / self.count
+		}
 	}
 }
 
-pub struct ImageCache {
+/// How long it took to decode the images and to upload them to the video memory
+#[derive(Debug, Clone, Default)]
+pub struct DecodeStats {
+	pub decode: TimingStats,
+	pub upload: TimingStats,
+}
+
+/// The cached textures of a display that is not the one in use. Textures can only be used on
+/// the display that created them.
+struct DisplayTextures<T> {
+	context: Rc<dyn Any>,
+	texture_cache: BTreeMap<OsString, CachedTexture<T>>,
+	thumbnail_cache: BTreeMap<ThumbnailKey, (fs::Metadata, TextureEntry<T>)>,
+}
+
+/// The texture, the name and the dimensions of the file that was advanced to
+type AdvancedImage<T> = (Rc<T>, OsString, (u32, u32));
+/// Called with the path of every requested file whose texture arrived in the cache
+type PrefetchCallback = Box<dyn FnMut(&Path)>;
+/// Called with the path of every requested file that couldn't be loaded and the error
+type PrefetchErrorCallback = Box<dyn FnMut(&Path, &Error)>;
+/// Tells if the file should be listed
+type FileFilter = Box<dyn Fn(&Path) -> bool>;
+type FileComparator = Box<dyn Fn(&Path, &Path) -> Ordering>;
+
+/// `T` is the type of the textures, which is only something else than a glium texture in
+/// the tests
+pub struct ImageCache<T = ImageTexture> {
 	dir_path: PathBuf,
 	//current_name: OsString,
 	current_index: usize,
 	dir_files: Vec<ImageDescriptor>,
+	/// The indices of the files in `dir_files` by their lowercase names
+	file_indices: BTreeMap<String, Vec<usize>>,
+	/// The cache keys of the loaded files by their identities on the disk
+	identity_keys: BTreeMap<FileIdentity, OsString>,
 
 	remaining_capacity: isize,
 	total_capacity: isize,
 	curr_est_size: isize,
 	requested_images: i32,
-	texture_cache: BTreeMap<OsString, CachedTexture>,
+	texture_cache: BTreeMap<OsString, CachedTexture<T>>,
+	thumbnail_remaining_capacity: isize,
+	thumbnail_total_capacity: isize,
+	thumbnail_cache: BTreeMap<ThumbnailKey, (fs::Metadata, TextureEntry<T>)>,
+	/// The dimensions read by `probe_dimensions`, keyed by the canonical path of the file
+	dimension_cache: BTreeMap<PathBuf, (fs::Metadata, (u32, u32))>,
+	supported_extensions: BTreeSet<String>,
+	eviction_policy: EvictionPolicy,
+	sort_order: SortOrder,
+	sort_direction: SortDirection,
+	recursive: bool,
+	/// Whether the last navigation moved towards the beginning of the directory
+	navigating_backward: bool,
+	watch_directory: bool,
+	dir_watcher: Option<DirWatcher>,
+	/// Whether large directories are listed on a separate thread
+	incremental_listing: bool,
+	/// Receives the files of the directory in batches while it's being listed on a separate
+	/// thread. The received files are added to `dir_files`, which starts out with only the
+	/// file that was opened. The channel disconnects once the listing is complete.
+	pending_listing: Option<Receiver<io::Result<Vec<PathBuf>>>>,
+	/// Whether `dir_files` only contains the file opened by `load_single` because the
+	/// directory hasn't been listed yet
+	listing_deferred: bool,
+	prefetch_callback: Option<PrefetchCallback>,
+	prefetch_error_callback: Option<PrefetchErrorCallback>,
+	/// Whether the decoding errors are reported as `CorruptImage`
+	report_corrupt_images: bool,
+	/// Replaces the check of the supported extensions when set
+	file_filter: Option<FileFilter>,
+	/// Replaces the sort order when set
+	file_comparator: Option<FileComparator>,
+	upload_budget: Option<usize>,
+	max_pending_prefetch_requests: i32,
+	/// Whether the files without an extension are checked for the signature of an image
+	sniff_extensionless: bool,
+	skip_hidden: bool,
+	/// Whether the decoded pixels of the still images are kept alongside their textures
+	keep_pixels: bool,
+	/// Whether the file that the directory was opened with is hidden. The hidden files are
+	/// listed in that case, otherwise the file itself couldn't be shown.
+	opened_hidden: bool,
+	/// Replaces the sampling hint of every texture when set
+	sampling_override: Option<SamplingHint>,
+	/// The cache keys of the files loaded by the ongoing `load_many` call
+	pinned_keys: BTreeSet<OsString>,
+	/// The canonical paths of the files pinned by `pin`
+	pinned_paths: BTreeSet<PathBuf>,
+	/// Whether moving past the last file goes to the first one and the other way around
+	wrap_around: bool,
+	/// The clockwise rotation in degrees of the files rotated by `rotate_current`, keyed by
+	/// their path
+	rotations: BTreeMap<PathBuf, u32>,
+	/// The files flipped by `flip_current`. These are mirrored horizontally before they are
+	/// rotated, a vertical flip is stored as a horizontal one and a rotation.
+	flipped: BTreeSet<PathBuf>,
+	max_entries: Option<usize>,
+	last_load_source: Option<LoadSource>,
+	/// The page of the current file that is displayed if it's a multi-page document
+	current_page: usize,
+	/// The context of the display that the textures in the cache belong to
+	context: Option<Rc<dyn Any>>,
+	other_displays: Vec<DisplayTextures<T>>,
+	stats: DecodeStats,
 
 	loader: ImageLoader,
 }
@@ -60,371 +551,2211 @@ pub struct ImageCache {
 /// This is a store for the supported images loaded from a folder
 ///
 /// The basic idea is to have a few images already in the memory while an image is shown on the screen
-impl ImageCache {
-	const MAX_PENDING_PREFETCH_REQUESTS: i32 = 4;
+impl<T: TextureSize> ImageCache<T> {
+	const DEFAULT_MAX_PENDING_PREFETCH_REQUESTS: i32 = 4;
+	/// The number of pending requests can't be set higher than this so that the request
+	/// queues of the threads don't grow without bounds
+	const PENDING_PREFETCH_REQUEST_LIMIT: i32 = 64;
 
 	/// # Arguments
 	/// * `capacity` - Number of bytes. The last image loaded will be the one at which the allocated memory reaches or exceeds capacity
-	pub fn new(capacity: isize, threads: u32) -> ImageCache {
-		ImageCache {
+	/// * `thumbnail_capacity` - Number of bytes that the thumbnails may take up, separately from `capacity`
+	pub fn new(capacity: isize, thumbnail_capacity: isize, threads: u32) -> ImageCache<T> {
+		// Only starting the threads can fail at the normal priority
+		ImageCache::with_thread_priority(
+			capacity,
+			thumbnail_capacity,
+			threads,
+			ThreadPriority::Normal,
+		)
+		.expect("Failed to start the image loader threads")
+	}
+
+	/// Same as `new` but the threads that decode the prefetched images are run at
+	/// `thread_priority`. Fails if the threads can't be started or their priority can't be
+	/// lowered.
+	pub fn with_thread_priority(
+		capacity: isize,
+		thumbnail_capacity: isize,
+		threads: u32,
+		thread_priority: ThreadPriority,
+	) -> Result<ImageCache<T>> {
+		Ok(ImageCache {
 			dir_path: PathBuf::new(),
 			current_index: 0,
 			dir_files: Vec::new(),
+			file_indices: BTreeMap::new(),
+			identity_keys: BTreeMap::new(),
 
 			remaining_capacity: capacity,
 			total_capacity: capacity,
 			curr_est_size: capacity,
 			requested_images: 0,
 			texture_cache: BTreeMap::new(),
+			thumbnail_remaining_capacity: thumbnail_capacity,
+			thumbnail_total_capacity: thumbnail_capacity,
+			thumbnail_cache: BTreeMap::new(),
+			dimension_cache: BTreeMap::new(),
+			supported_extensions: DEFAULT_SUPPORTED_EXTENSIONS
+				.iter()
+				.chain(HEIF_EXTENSIONS.iter())
+				.chain(SVG_EXTENSIONS.iter())
+				.chain(RAW_EXTENSIONS.iter())
+				.chain(COMPRESSED_EXTENSIONS.iter())
+				.map(|ext| ext.to_string())
+				.collect(),
+			eviction_policy: EvictionPolicy::Positional,
+			sort_order: SortOrder::Natural,
+			sort_direction: SortDirection::Ascending,
+			recursive: false,
+			navigating_backward: false,
+			watch_directory: false,
+			dir_watcher: None,
+			incremental_listing: false,
+			pending_listing: None,
+			listing_deferred: false,
+			prefetch_callback: None,
+			prefetch_error_callback: None,
+			report_corrupt_images: false,
+			file_filter: None,
+			file_comparator: None,
+			upload_budget: None,
+			max_pending_prefetch_requests: Self::DEFAULT_MAX_PENDING_PREFETCH_REQUESTS,
+			sniff_extensionless: false,
+			skip_hidden: true,
+			keep_pixels: false,
+			opened_hidden: false,
+			sampling_override: None,
+			pinned_keys: BTreeSet::new(),
+			pinned_paths: BTreeSet::new(),
+			wrap_around: true,
+			rotations: BTreeMap::new(),
+			flipped: BTreeSet::new(),
+			max_entries: None,
+			last_load_source: None,
+			current_page: 0,
+			context: None,
+			other_displays: Vec::new(),
+			stats: DecodeStats::default(),
+
+			loader: ImageLoader::new(threads, thread_priority)?,
+		})
+	}
 
-			loader: ImageLoader::new(threads),
-		}
+	/// Sets the file extensions that are considered to be supported images. The comparison
+	/// is case insensitive. The directory listing is only updated with the next call to
+	/// `update_directory` or when a file from a different directory is loaded.
+	pub fn set_supported_extensions<I, S>(&mut self, extensions: I)
+	where
+		I: IntoIterator<Item = S>,
+		S: AsRef<str>,
+	{
+		self.supported_extensions =
+			extensions.into_iter().map(|ext| ext.as_ref().to_lowercase()).collect();
 	}
 
-	pub fn cached_from_dir(&self) -> Vec<bool> {
-		let mut result = Vec::with_capacity(self.dir_files.len());
+	pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+		self.eviction_policy = policy;
+	}
 
-		for i in 0..self.dir_files.len() {
-			let file_name = self.dir_files[i].dir_entry.file_name();
-			result.push(self.texture_cache.contains_key(&file_name));
-		}
+	/// Sorts the files of the current directory again and keeps the current file the same.
+	pub fn set_sort_order(
+		&mut self,
+		sort_order: SortOrder,
+		direction: SortDirection,
+	) -> Result<()> {
+		self.sort_order = sort_order;
+		self.sort_direction = direction;
+		self.update_open_directory()
+	}
 
-		result
+	/// Only the files for which `filter` returns true are listed in the directory. This
+	/// replaces the check of the supported extensions.
+	pub fn set_file_filter<F>(&mut self, filter: F) -> Result<()>
+	where
+		F: Fn(&Path) -> bool + 'static,
+	{
+		self.file_filter = Some(Box::new(filter));
+		self.update_open_directory()
 	}
 
-	pub fn current_filename(&self) -> OsString {
-		match self.dir_files.get(self.current_index) {
-			Some(desc) => desc.dir_entry.file_name(),
-			None => OsString::new(),
-		}
+	/// Goes back to listing the files with a supported extension
+	pub fn clear_file_filter(&mut self) -> Result<()> {
+		self.file_filter = None;
+		self.update_open_directory()
 	}
 
-	pub fn current_file_path(&self) -> PathBuf {
-		self.dir_path.join(self.current_filename())
+	/// When enabled, the files without an extension are listed if their first few bytes look
+	/// like an image. This reads from every such file when the directory is opened.
+	pub fn set_sniff_extensionless(&mut self, enabled: bool) -> Result<()> {
+		self.sniff_extensionless = enabled;
+		self.update_open_directory()
 	}
 
-	pub fn current_file_index(&self) -> usize {
-		self.current_index
+	/// When enabled, which is the default, the hidden files are not listed, unless the
+	/// directory was opened with a hidden file.
+	pub fn set_skip_hidden(&mut self, enabled: bool) -> Result<()> {
+		self.skip_hidden = enabled;
+		self.update_open_directory()
 	}
 
-	pub fn current_dir_len(&self) -> usize {
-		self.dir_files.len()
+	fn lists_hidden(&self) -> bool {
+		!self.skip_hidden || self.opened_hidden
 	}
 
-	/// Fetches the contents of the folder and stores the list of image filenames to know which
-	/// files will be the next and previous.
-	///
-	/// Tries to locate the image that was the current image before calling the function and
-	/// keeping it current. If that filename is not found, than it tries to preserve the previous
-	/// file index instead of the filename. If there is no such an index in the folder, it resets
-	/// the index to 0 making the current file the first one in the folder.
-	///
-	/// Returns the error that might occure while fetching the files from the directory. Otherwise
-	/// returns `Ok(())`
-	pub fn update_directory(&mut self) -> Result<()> {
-		let curr_filename = self.current_filename();
-		self.dir_files = Self::collect_directory(self.dir_path.as_path())?;
+	/// Sorts the files of a directory with `comparator` instead of the sort order. The sort
+	/// direction still applies.
+	pub fn set_file_comparator<F>(&mut self, comparator: F) -> Result<()>
+	where
+		F: Fn(&Path, &Path) -> Ordering + 'static,
+	{
+		self.file_comparator = Some(Box::new(comparator));
+		self.update_open_directory()
+	}
 
-		for (index, desc) in self.dir_files.iter().enumerate() {
-			if desc.dir_entry.file_name() == curr_filename {
-				self.current_index = index;
-				return Ok(());
-			}
-		}
+	/// Goes back to sorting the files by the sort order
+	pub fn clear_file_comparator(&mut self) -> Result<()> {
+		self.file_comparator = None;
+		self.update_open_directory()
+	}
 
-		if self.dir_files.len() > self.current_index {
-			return Ok(());
-		} else if !self.dir_files.is_empty() {
-			self.current_index = 0;
+	/// When disabled, moving past the last or the first file fails with `EndOfDirectory` and
+	/// longer jumps stop at the last or the first file. Enabled by default.
+	pub fn set_wrap_around(&mut self, enabled: bool) {
+		self.wrap_around = enabled;
+	}
+
+	/// When enabled, the files in the subdirectories are also part of the list of files, as
+	/// if they were all in the directory that was opened. The files of a directory come
+	/// before the files of its subdirectories and the files are sorted within each directory.
+	pub fn set_recursive(&mut self, recursive: bool) -> Result<()> {
+		self.recursive = recursive;
+		self.set_watch_directory(self.watch_directory)?;
+		self.update_open_directory()
+	}
+
+	/// Calls `update_directory` if there's a directory open
+	fn update_open_directory(&mut self) -> Result<()> {
+		if self.dir_path.as_os_str().is_empty() {
 			return Ok(());
 		}
+		self.update_directory()
+	}
 
+	/// When enabled, the directory of the current file is watched for changes and the list of
+	/// files is refreshed before the next navigation if files were added or removed.
+	pub fn set_watch_directory(&mut self, enabled: bool) -> Result<()> {
+		self.watch_directory = enabled;
+		self.dir_watcher = None;
+		if enabled && !self.dir_path.as_os_str().is_empty() {
+			self.dir_watcher = Some(DirWatcher::new(self.dir_path.as_path(), self.recursive)?);
+		}
 		Ok(())
 	}
 
-	pub fn load_at_index(
-		&mut self,
-		display: &glium::Display,
-		index: usize,
-	) -> Result<(Rc<SrgbTexture2d>, OsString)> {
-		let path = self
-			.dir_files
-			.get(index)
-			.ok_or_else(|| {
-				format!(
-					"Index {} is out of bounds of the current directory '{}'",
-					index,
-					self.dir_path.to_str().unwrap()
-				)
-			})?
-			.dir_entry
-			.path();
+	/// When enabled, opening a file displays it right away and the rest of its directory is
+	/// listed on a separate thread, which helps with directories of many thousands of files.
+	/// Navigating before the listing finishes moves between the files that were listed so far.
+	/// Archives and recursive listings are always read on the calling thread.
+	pub fn set_incremental_listing(&mut self, enabled: bool) {
+		self.incremental_listing = enabled;
+	}
 
-		let result = self.load_specific(display, &path)?;
+	/// Changes the number of bytes that the cached textures may take up. If the cache holds
+	/// more than that, textures are evicted in the order of the eviction policy until the rest
+	/// fits within the new capacity. Pinned textures are never evicted.
+	pub fn set_capacity(&mut self, capacity: isize) {
+		self.remaining_capacity += capacity - self.total_capacity;
+		self.total_capacity = capacity;
+		for path in self.eviction_candidates() {
+			if self.remaining_capacity >= 0 {
+				break;
+			}
+			if let Some(texture) = self.texture_cache.remove(&path) {
+				self.remaining_capacity += texture.size_estimate();
+			}
+		}
+	}
 
-		self.current_index = index;
+	/// Limits the number of textures in the cache regardless of their size, for directories
+	/// with lots of small images. Textures are evicted in the order of the eviction policy
+	/// when there are more. `None` means no limit.
+	pub fn set_max_entries(&mut self, max_entries: Option<usize>) {
+		self.max_entries = max_entries;
+		let current = self.current_cache_key();
+		self.enforce_max_entries(&current);
+	}
 
-		Ok((result, path.file_name().unwrap_or_else(|| OsStr::new("")).to_owned()))
+	/// The number of entries in the cache that hold a texture
+	fn texture_count(&self) -> usize {
+		self.texture_cache
+			.values()
+			.filter(|cached| !matches!(cached, CachedTexture::LoadRequested))
+			.count()
 	}
 
-	pub fn load_specific(
-		&mut self,
-		display: &glium::Display,
-		path: &Path,
-	) -> Result<Rc<SrgbTexture2d>> {
-		use std::collections::btree_map::Entry;
+	/// Evicts textures until the entry count limit is met. The texture under `keep`, which
+	/// was usually just inserted, is never evicted.
+	fn enforce_max_entries(&mut self, keep: &OsStr) {
+		let max_entries = match self.max_entries {
+			Some(max_entries) => max_entries,
+			None => return,
+		};
+		let mut count = self.texture_count();
+		for key in self.eviction_candidates() {
+			if count <= max_entries {
+				break;
+			}
+			if key == keep {
+				continue;
+			}
+			if let Some(texture) = self.texture_cache.remove(&key) {
+				self.remaining_capacity += texture.size_estimate();
+				count -= 1;
+			}
+		}
+	}
 
-		let path = path.canonicalize()?;
+	/// Sets a function that is called from `process_prefetched` with the path of every file
+	/// whose requested texture was inserted into the cache. Replaces the previous callback.
+	pub fn set_prefetch_callback<F>(&mut self, callback: F)
+	where
+		F: FnMut(&Path) + 'static,
+	{
+		self.prefetch_callback = Some(Box::new(callback));
+	}
 
-		let target_file_name = match path.file_name() {
-			Some(filename) => filename.to_owned(),
-			None => bail!(format!("Could not get filename from path '{}'", path.to_str().unwrap())),
-		};
+	pub fn clear_prefetch_callback(&mut self) {
+		self.prefetch_callback = None;
+	}
 
-		let parent = path.parent().ok_or("Could not get parent directory")?.to_owned();
+	/// Sets a function that is called from `process_prefetched` with the path and the error
+	/// of every file that the loader threads failed to load. Replaces the previous callback.
+	pub fn set_prefetch_error_callback<F>(&mut self, callback: F)
+	where
+		F: FnMut(&Path, &Error) + 'static,
+	{
+		self.prefetch_error_callback = Some(Box::new(callback));
+	}
 
-		// Lets just process incoming images
-		self.process_prefetched(display)?;
+	pub fn clear_prefetch_error_callback(&mut self) {
+		self.prefetch_error_callback = None;
+	}
 
-		if self.dir_path != parent {
-			self.texture_cache.clear();
-			self.remaining_capacity = self.total_capacity;
-			self.change_directory(parent, target_file_name.clone())?;
-		} else {
-			for (index, desc) in self.dir_files.iter().enumerate() {
-				if desc.dir_entry.file_name() == target_file_name {
-					self.current_index = index;
-				}
-			}
+	/// When enabled, the files that can be read but aren't valid images, for example because
+	/// they are empty or truncated, fail to load with `CorruptImage` instead of the error of
+	/// the decoder. This tells them apart from the files that couldn't be read. Disabled by
+	/// default.
+	pub fn set_report_corrupt_images(&mut self, enabled: bool) {
+		self.report_corrupt_images = enabled;
+	}
 
-			// Delete all entries that are outside the window of files around the current file
-			// allowed by the capacity
-			// And there is just one more thing left to do...
-			// Walk through our list of directory entries sorted by their distance from the current
-			// file and in each step remove an entry from the cache until we reach the desired cache
-			// size
+	/// Limits the number of prefetched images that `process_prefetched` uploads to the GPU in
+	/// a single call. The rest are uploaded by the following calls. `None` means no limit.
+	pub fn set_upload_budget(&mut self, budget: Option<usize>) {
+		self.upload_budget = budget;
+	}
 
-			let (mut new_cache, remaining_capacity) = {
-				let mut sorted_files: Vec<_> =
-					self.texture_cache.iter().enumerate().rev().collect();
-				sorted_files.sort_by_key(|(index, _)| {
-					(*index as isize - self.current_index as isize).abs()
-				});
+	/// Limits the number of prefetched images that are held decoded while waiting for
+	/// `process_prefetched` to upload them. The loader threads stop decoding until there's
+	/// room again, which bounds the memory used for the images that the upload budget leaves
+	/// for later. `None` means no limit.
+	pub fn set_max_decoded_images(&mut self, limit: Option<usize>) {
+		self.loader.set_max_decoded(limit);
+	}
 
-				let mut remaining_capacity = self.total_capacity;
-				//let mut est_file_capacity = self.total_capacity / self.curr_est_size;
-				let mut new_cache = BTreeMap::new();
-				for (_, (path, texture)) in sorted_files.into_iter() {
-					match texture {
-						CachedTexture::LoadRequested => {
-							new_cache.insert(path.clone(), CachedTexture::LoadRequested);
-						}
-						CachedTexture::Texture(texture) => {
-							// Thew new file has to fit in the cache after this operation
-							// which is why we multiply the estimated size by two
-							if remaining_capacity > (self.curr_est_size * 2) {
-								let dimensions = (texture.1.width(), texture.1.height());
-								remaining_capacity -= get_image_size_estimate(dimensions) as isize;
-								new_cache
-									.insert(path.clone(), CachedTexture::Texture(texture.clone()));
-							}
-						}
-					}
-				}
+	/// Sets how many prefetch requests may be waiting for the loader threads at the same time.
+	/// Larger caches fill up faster with more requests. The value is clamped between 1 and 64.
+	pub fn set_max_pending_prefetch_requests(&mut self, count: i32) {
+		self.max_pending_prefetch_requests = count.clamp(1, Self::PENDING_PREFETCH_REQUEST_LIMIT);
+	}
 
-				(new_cache, remaining_capacity)
-			};
+	/// Changes the number of threads that decode the prefetched images, for example to use
+	/// fewer of them while running on battery. At least one thread is kept. The stopped threads
+	/// finish the request they are decoding without blocking the caller, their other requests
+	/// are left to the remaining threads. Fails if a new thread can't be started or its
+	/// priority can't be lowered.
+	pub fn set_worker_threads(&mut self, threads: u32) -> Result<()> {
+		Ok(self.loader.set_thread_count(threads)?)
+	}
 
-			mem::swap(&mut self.texture_cache, &mut new_cache);
-			self.remaining_capacity = remaining_capacity;
-		}
+	pub fn worker_threads(&self) -> u32 {
+		self.loader.thread_count()
+	}
 
-		let metadata = fs::metadata(path.as_path())?;
+	/// Sets the number of pending prefetch requests so that the cache can be filled with
+	/// images of the size of the last loaded image.
+	pub fn fit_pending_prefetch_requests_to_capacity(&mut self) {
+		let images_per_cache = self.total_capacity / self.curr_est_size.max(1);
+		self.set_max_pending_prefetch_requests(images_per_cache.min(i32::MAX as isize) as i32);
+	}
 
-		// Check if it is inside the texture cache first
-		{
-			let texture_entry = self.texture_cache.entry(target_file_name.clone());
-			if let Entry::Occupied(ref entry) = texture_entry {
-				if let CachedTexture::Texture(ref entry) = entry.get() {
-					if entry.0.modified().unwrap() == metadata.modified().unwrap() {
-						return Ok(entry.1.clone());
-					}
-				}
-			}
-		}
+	/// Timings of every image decoded and uploaded by this cache so far
+	pub fn stats(&self) -> &DecodeStats {
+		&self.stats
+	}
 
-		let image = load_image(path.as_path())?;
-		self.curr_est_size = get_image_size_estimate((image.width(), image.height())) as isize;
-		let image_size_estimate = self.curr_est_size;
-		if self.remaining_capacity < image_size_estimate {
-			self.texture_cache.clear();
-			self.remaining_capacity = self.total_capacity;
-		}
-		self.remaining_capacity -= image_size_estimate;
+	/// Returns the estimated number of bytes used by the cached textures and the total capacity
+	/// of the cache in bytes.
+	pub fn cache_usage(&self) -> (isize, isize) {
+		(self.total_capacity - self.remaining_capacity, self.total_capacity)
+	}
 
-		let result_texture = Rc::new(texture_from_image(display, image)?);
-		match self.texture_cache.entry(target_file_name) {
-			Entry::Vacant(entry) => {
-				entry.insert(CachedTexture::Texture((metadata, result_texture.clone())));
-			}
-			Entry::Occupied(mut entry) => match entry.get_mut() {
-				entry @ CachedTexture::LoadRequested => {
-					*entry = CachedTexture::Texture((metadata, result_texture.clone()));
-				}
-				CachedTexture::Texture(ref mut entry) => {
-					if entry.0.modified().unwrap() != metadata.modified().unwrap() {
-						*entry = (metadata, result_texture.clone());
-					}
-				}
-			},
-		}
+	/// Tells whether the texture returned by the last successful load came from the cache or
+	/// had to be decoded. `None` before the first load.
+	pub fn last_load_source(&self) -> Option<LoadSource> {
+		self.last_load_source
+	}
 
-		Ok(result_texture)
+	/// The number of requested files whose textures haven't arrived in the cache yet
+	pub fn pending_count(&self) -> usize {
+		self.texture_cache
+			.values()
+			.filter(|cached| matches!(cached, CachedTexture::LoadRequested))
+			.count()
 	}
 
-	pub fn load_next(&mut self, display: &glium::Display) -> Result<(Rc<SrgbTexture2d>, OsString)> {
-		self.load_jump(display, 1)
+	/// Images larger than `max_dimension` in either direction are scaled down to fit when they
+	/// are loaded. `None` keeps the original size. The textures that are already in the cache
+	/// are discarded because they were decoded with the previous setting.
+	pub fn set_max_dimension(&mut self, max_dimension: Option<u32>) {
+		let mut settings = self.loader.settings();
+		settings.max_dimension = max_dimension;
+		self.change_load_settings(settings);
 	}
 
-	pub fn load_prev(&mut self, display: &glium::Display) -> Result<(Rc<SrgbTexture2d>, OsString)> {
-		self.load_jump(display, -1)
+	/// JPEGs larger than `max_dimension` in either direction are decoded at a reduced
+	/// resolution, so that their full resolution pixels are never allocated. Unlike
+	/// `set_max_dimension` this bounds the memory used while decoding, not only the size of
+	/// the textures. Other formats are still decoded at their original size, only
+	/// `set_max_dimension` scales them down. `None` decodes the JPEGs at their original size.
+	pub fn set_max_decode_dimension(&mut self, max_dimension: Option<u32>) {
+		let mut settings = self.loader.settings();
+		settings.max_decode_dimension = max_dimension;
+		self.change_load_settings(settings);
 	}
 
-	pub fn load_jump(
-		&mut self,
-		display: &glium::Display,
-		jump_count: i32,
-	) -> Result<(Rc<SrgbTexture2d>, OsString)> {
-		if jump_count == 0 {
-			let filename = self.current_filename();
-			return Ok((
-				match self.texture_cache.get(&filename) {
-					Some(CachedTexture::Texture(ref entry)) => entry.1.clone(),
-					_ => bail!(Error::from("Could not find current file in cache.")),
-				},
-				filename,
-			));
+	/// When enabled, the colors of images with an embedded ICC profile are converted to sRGB.
+	/// Images without a profile are assumed to be sRGB already. The cached textures and
+	/// thumbnails are discarded if the setting changes.
+	pub fn set_color_management(&mut self, enabled: bool) {
+		let mut settings = self.loader.settings();
+		settings.color_management = enabled;
+		self.change_load_settings(settings);
+	}
+
+	/// Sets how many times reading a file is retried after an IO error and how long to wait
+	/// before the first retry. The wait doubles with every retry. Zero retries, the default,
+	/// turns retrying off.
+	///
+	/// Only the loader threads wait between the retries. If `load_specific` can't read the
+	/// file, it returns the error and requests the file from the loader threads, so the
+	/// texture becomes available through `process_prefetched` once a retry succeeds.
+	pub fn set_io_retries(&mut self, retries: u32, initial_delay: Duration) {
+		let mut settings = self.loader.settings();
+		settings.retry = RetryPolicy { retries, initial_delay };
+		// This doesn't change how the images look, so the cached textures are kept
+		self.loader.set_settings(settings);
+	}
+
+	/// When enabled, the loader hashes the contents of every file it loads and the hash is
+	/// stored with the texture. The file counts as modified if the hash changed, even if the
+	/// modification time is the same, so that it's loaded again and reported by
+	/// `stale_entries`. This is for file systems and editors that don't update the
+	/// modification time reliably.
+	pub fn set_content_hashing(&mut self, enabled: bool) {
+		let mut settings = self.loader.settings();
+		settings.content_hashing = enabled;
+		self.loader.set_settings(settings);
+		if !enabled {
+			for cached in self.texture_cache.values_mut() {
+				cached.set_content_hash(None);
+			}
+		}
+	}
+
+	/// Bakes a gamma and an exposure adjustment into the textures. The cached textures and
+	/// thumbnails are dropped when the adjustment changes so that they are loaded again with
+	/// the new one. A gamma of 1 and an exposure of 0 leave the colors unchanged.
+	pub fn set_color_adjustment(&mut self, gamma: f32, exposure: f32) -> Result<()> {
+		if !gamma.is_finite() || gamma <= 0.0 || !exposure.is_finite() {
+			bail!(format!("Invalid color adjustment, gamma {} and exposure {}", gamma, exposure));
+		}
+		let mut settings = self.loader.settings();
+		settings.adjustment = ColorAdjustment { gamma, exposure };
+		self.change_load_settings(settings);
+		Ok(())
+	}
+
+	/// When disabled, the colors of the images are treated as linear values instead of sRGB
+	/// encoded ones, which is how normal maps and other data textures are stored. The cached
+	/// textures and thumbnails are discarded if the setting changes.
+	pub fn set_srgb(&mut self, enabled: bool) {
+		let mut settings = self.loader.settings();
+		settings.srgb = enabled;
+		self.change_load_settings(settings);
+	}
+
+	/// Premultiplies the colors of the images with their alpha when enabled. The images are
+	/// loaded with straight alpha by default.
+	pub fn set_premultiplied_alpha(&mut self, enabled: bool) {
+		let mut settings = self.loader.settings();
+		settings.premultiply_alpha = enabled;
+		self.change_load_settings(settings);
+	}
+
+	/// The resolution that SVG images are rasterized at, which is 96 by default. It only
+	/// affects the lengths given in physical units. The cached textures and thumbnails, and
+	/// the dimensions of the SVG images are discarded if the resolution changes.
+	pub fn set_svg_dpi(&mut self, dpi: f32) {
+		let mut settings = self.loader.settings();
+		settings.svg_dpi = dpi;
+		if self.change_load_settings(settings) {
+			self.dimension_cache.retain(|path, _| !has_extension(path, SVG_EXTENSIONS));
+		}
+	}
+
+	/// Replaces the decoder of the images, for example with a stub in tests. The cached
+	/// textures, thumbnails and dimensions are dropped because they came from the previous
+	/// decoder.
+	pub fn set_decoder(&mut self, decoder: Arc<dyn Decoder>) {
+		self.loader.cancel_pending_requests();
+		self.loader.set_decoder(decoder);
+		self.invalidate_textures();
+		self.dimension_cache.clear();
+	}
+
+	/// Passes the settings to the loader and drops the cached textures and thumbnails if they
+	/// are different from the current ones. Returns whether the settings changed.
+	fn change_load_settings(&mut self, settings: LoadSettings) -> bool {
+		if self.loader.settings() == settings {
+			return false;
+		}
+		self.loader.set_settings(settings);
+		self.invalidate_textures();
+		true
+	}
+
+	/// Drops every cached texture and thumbnail, for example because they were loaded with
+	/// settings that changed since
+	fn invalidate_textures(&mut self) {
+		self.texture_cache.clear();
+		self.remaining_capacity = self.total_capacity;
+		self.thumbnail_cache.clear();
+		self.thumbnail_remaining_capacity = self.thumbnail_total_capacity;
+	}
+
+	/// Borrows the texture of the current file if it has been loaded. Unlike the load methods
+	/// this doesn't count as an access for the eviction policy.
+	pub fn current_texture(&self) -> Option<&T> {
+		self.texture_cache.get(&self.current_cache_key())?.texture_ref()
+	}
+
+	/// The index of the page of the current file that was returned last, this is always 0
+	/// unless the file is a multi-page document
+	pub fn current_page(&self) -> usize {
+		self.current_page
+	}
+
+	/// The number of pages of the current file, or 0 if it hasn't been loaded yet
+	pub fn current_page_count(&self) -> usize {
+		self.texture_cache.get(&self.current_cache_key()).map_or(0, |cached| cached.page_count())
+	}
+
+	/// Keeps the decoded pixels of the still images that are loaded from now on, so that
+	/// they're available through `current_pixels` without decoding the file again. This
+	/// doubles the memory taken up by these images, which counts towards the capacity.
+	pub fn set_keep_pixels(&mut self, enabled: bool) {
+		self.keep_pixels = enabled;
+	}
+
+	/// The decoded pixels of the current file if it's a still image that was loaded while
+	/// `set_keep_pixels` was enabled
+	pub fn current_pixels(&self) -> Option<&image::RgbaImage> {
+		self.texture_cache.get(&self.current_cache_key())?.pixels()
+	}
+
+	/// The format and the color type of the current file, or `None` if the file hasn't been
+	/// loaded yet or it's in a format that isn't decoded by the `image` crate
+	pub fn current_format(&self) -> Option<ImageInfo> {
+		self.texture_cache.get(&self.current_cache_key())?.info()
+	}
+
+	/// The filtering that suits the current file best when it's magnified, or `None` if the
+	/// file hasn't been loaded yet. This is the override if one is set.
+	pub fn current_sampling_hint(&self) -> Option<SamplingHint> {
+		let hint = self.texture_cache.get(&self.current_cache_key())?.sampling()?;
+		Some(self.sampling_override.unwrap_or(hint))
+	}
+
+	/// Makes `current_sampling_hint` return `hint` for every file instead of the hint that was
+	/// determined from the image. `None` removes the override.
+	pub fn set_sampling_override(&mut self, hint: Option<SamplingHint>) {
+		self.sampling_override = hint;
+	}
+
+	/// Returns the frames of the current file if it is an animation that has been loaded
+	pub fn current_animation(&self) -> Option<&[(Rc<T>, Duration)]> {
+		match self.texture_cache.get(&self.current_cache_key()) {
+			Some(CachedTexture::Animation((_, entry))) => Some(entry.frames.as_slice()),
+			_ => None,
+		}
+	}
+
+	pub fn cached_from_dir(&self) -> Vec<bool> {
+		let mut result = Vec::with_capacity(self.dir_files.len());
+
+		for i in 0..self.dir_files.len() {
+			let cache_key = &self.dir_files[i].cache_key;
+			result.push(self.texture_cache.contains_key(cache_key));
+		}
+
+		result
+	}
+
+	pub fn current_filename(&self) -> OsString {
+		match self.dir_files.get(self.current_index) {
+			Some(desc) => desc.name.clone(),
+			None => OsString::new(),
+		}
+	}
+
+	fn current_cache_key(&self) -> OsString {
+		match self.dir_files.get(self.current_index) {
+			Some(desc) => desc.cache_key.clone(),
+			None => OsString::new(),
+		}
+	}
+
+	/// Returns the key under which the texture of the file in the current directory is cached
+	fn cache_key(&self, name: &OsStr) -> OsString {
+		match self.find_file(self.dir_path.as_path(), name) {
+			Some(index) => self.dir_files[index].cache_key.clone(),
+			None => name.to_owned(),
+		}
+	}
+
+	/// Same as `cache_key` but the file is about to be loaded, so it's looked up whether it's
+	/// the same file on the disk as one that's already in the cache
+	fn shared_cache_key(&mut self, name: &OsStr) -> OsString {
+		match self.find_file(self.dir_path.as_path(), name) {
+			Some(index) => self.resolve_cache_key(index),
+			None => name.to_owned(),
+		}
+	}
+
+	/// Makes the file at `index` use the cache entry of the file that is the same file on the
+	/// disk, if it was loaded under another name. Only the files that aren't cached yet are
+	/// inspected, so neither listing nor browsing the directory needs to query every file.
+	fn resolve_cache_key(&mut self, index: usize) -> OsString {
+		let desc = &mut self.dir_files[index];
+		if !self.texture_cache.contains_key(&desc.cache_key) {
+			if let Some(identity) = file_identity(desc.path.as_path()) {
+				let key =
+					self.identity_keys.entry(identity).or_insert_with(|| desc.cache_key.clone());
+				if self.texture_cache.contains_key(key) {
+					desc.cache_key = key.clone();
+				} else {
+					*key = desc.cache_key.clone();
+				}
+			}
+		}
+		desc.cache_key.clone()
+	}
+
+	pub fn current_file_path(&self) -> PathBuf {
+		self.dir_path.join(self.current_filename())
+	}
+
+	pub fn current_file_index(&self) -> usize {
+		self.current_index
+	}
+
+	pub fn current_dir_len(&self) -> usize {
+		self.dir_files.len()
+	}
+
+	/// Returns the zero based position of the current file and the number of files in the
+	/// directory, or `None` if the directory is empty.
+	pub fn current_index(&self) -> Option<(usize, usize)> {
+		if self.current_index < self.dir_files.len() {
+			Some((self.current_index, self.dir_files.len()))
+		} else {
+			None
+		}
+	}
+
+	/// The names of the supported files in the current directory in the order they are
+	/// navigated through. The names are relative to the opened directory.
+	pub fn file_names(&self) -> impl Iterator<Item = &OsString> {
+		self.dir_files.iter().map(|desc| &desc.name)
+	}
+
+	/// Fetches the contents of the folder and stores the list of image filenames to know which
+	/// files will be the next and previous.
+	///
+	/// Tries to locate the image that was the current image before calling the function and
+	/// keeping it current. If that filename is not found, than it tries to preserve the previous
+	/// file index instead of the filename. If there is no such an index in the folder, the last
+	/// file of the folder becomes the current one, as that is the closest to the previous index.
+	///
+	/// Returns the error that might occure while fetching the files from the directory. Otherwise
+	/// returns `Ok(())`
+	pub fn update_directory(&mut self) -> Result<()> {
+		// The directory is read again anyway
+		self.pending_listing = None;
+		self.listing_deferred = false;
+		let curr_filename = self.current_filename();
+		let dir_files = self.collect_directory(self.dir_path.as_path())?;
+		self.set_dir_files(dir_files);
+
+		// Drop the textures of the files that are no longer in the directory
+		let names: BTreeSet<_> = self.dir_files.iter().map(|desc| &desc.cache_key).collect();
+		let removed: Vec<_> = self
+			.texture_cache
+			.iter()
+			.filter(|(name, cached)| cached.metadata().is_some() && !names.contains(name))
+			.map(|(name, _)| name.clone())
+			.collect();
+		for name in removed {
+			if let Some(cached) = self.texture_cache.remove(&name) {
+				self.remaining_capacity += cached.size_estimate();
+			}
+		}
+
+		if let Some(index) = self.find_file(self.dir_path.as_path(), &curr_filename) {
+			self.current_index = index;
+			return Ok(());
+		}
+
+		if self.dir_files.len() > self.current_index {
+			return Ok(());
+		} else if !self.dir_files.is_empty() {
+			self.current_index = self.dir_files.len() - 1;
+			return Ok(());
+		}
+
+		Ok(())
+	}
+
+	/// Calls `update_directory` if the directory watcher detected that files were added,
+	/// removed or renamed.
+	fn update_directory_if_changed(&mut self) -> Result<()> {
+		self.receive_listing()?;
+		let changed = match self.dir_watcher {
+			Some(ref watcher) => watcher.listing_changed(),
+			None => false,
+		};
+		if changed {
+			self.update_directory()?;
+		}
+		Ok(())
+	}
+
+	pub fn load_at_index(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		index: usize,
+	) -> Result<(Rc<T>, OsString, (u32, u32))> {
+		self.list_deferred_directory()?;
+		self.update_directory_if_changed()?;
+		if self.dir_files.is_empty() {
+			bail!(ErrorKind::EmptyDirectory);
+		}
+		let path = self
+			.dir_files
+			.get(index)
+			.ok_or_else(|| {
+				format!(
+					"Index {} is out of bounds of the current directory '{}'",
+					index,
+					self.dir_path.to_str().unwrap()
+				)
+			})?
+			.path
+			.clone();
+
+		let result = self.load_texture(display, &path)?;
+
+		self.current_index = index;
+
+		let dimensions = result.texture_dimensions();
+		Ok((result, path.file_name().unwrap_or_else(|| OsStr::new("")).to_owned(), dimensions))
+	}
+
+	/// Loads the first file after the current one whose name satisfies `predicate`, wrapping
+	/// around at the end of the directory. The current file is checked last. The names are
+	/// relative to the opened directory.
+	pub fn load_matching<F>(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		predicate: F,
+	) -> Result<(Rc<T>, OsString, (u32, u32))>
+	where
+		F: Fn(&OsStr) -> bool,
+	{
+		self.list_deferred_directory()?;
+		self.update_directory_if_changed()?;
+		if self.dir_files.is_empty() {
+			bail!(ErrorKind::EmptyDirectory);
+		}
+		let len = self.dir_files.len();
+		let index = (1..=len)
+			.map(|offset| (self.current_index + offset) % len)
+			.find(|&index| predicate(&self.dir_files[index].name))
+			.ok_or(ErrorKind::NoMatchingFile)?;
+		self.load_at_index(display, index)
+	}
+
+	/// Loads the first file after the current one whose name starts with `prefix`, ignoring
+	/// the case, like the type-ahead search of file browsers
+	pub fn load_with_prefix(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		prefix: &str,
+	) -> Result<(Rc<T>, OsString, (u32, u32))> {
+		let prefix = prefix.to_lowercase();
+		self.load_matching(display, |name| {
+			name.to_string_lossy().to_lowercase().starts_with(&prefix)
+		})
+	}
+
+	/// Loads the file without listing its directory, so that a single file opened in a huge
+	/// directory is shown right away. The directory is listed when navigating to another
+	/// file for the first time.
+	pub fn load_single(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		path: &Path,
+	) -> Result<(Rc<T>, (u32, u32))> {
+		let path = resolve_path(path)?;
+		let (parent, file_name) = self.locate_file(path.as_path())?;
+		if self.dir_path != parent {
+			self.listing_deferred = true;
+			if let Err(err) = self.switch_directory(parent, Some(file_name)) {
+				self.listing_deferred = false;
+				return Err(err);
+			}
+		}
+		self.load_specific(display, &path)
+	}
+
+	/// Lists the directory if only the file opened by `load_single` is known
+	fn list_deferred_directory(&mut self) -> Result<()> {
+		if self.listing_deferred {
+			self.update_directory()?;
+		}
+		Ok(())
+	}
+
+	/// Loads the file and returns its texture along with the dimensions of the texture
+	pub fn load_specific(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		path: &Path,
+	) -> Result<(Rc<T>, (u32, u32))> {
+		let texture = self.load_texture(display, path)?;
+		let dimensions = texture.texture_dimensions();
+		Ok((texture, dimensions))
+	}
+
+	fn load_texture(&mut self, display: &impl Uploader<Texture = T>, path: &Path) -> Result<Rc<T>> {
+		use std::collections::btree_map::Entry;
+
+		// Documents are always opened on their first page
+		self.current_page = 0;
+		self.select_display(display);
+		let path = resolve_path(path)?;
+
+		let (parent, target_file_name) = self.locate_file(path.as_path())?;
+
+		// Lets just process incoming images
+		match self.process_prefetched(display) {
+			// The requested file is loaded on this thread anyway
+			Err(Error(ErrorKind::LoaderDisconnected, _)) => (),
+			result => result?,
+		}
+
+		if self.dir_path != parent {
+			self.listing_deferred = false;
+			self.switch_directory(parent, Some(target_file_name.clone()))?;
+		} else {
+			if let Some(index) = self.find_file(self.dir_path.as_path(), &target_file_name) {
+				self.current_index = index;
+			}
+
+			// Delete all entries that are outside the window of files around the current file
+			// allowed by the capacity
+			// And there is just one more thing left to do...
+			// Walk through our list of directory entries sorted by their distance from the current
+			// file (or by how recently they were accessed) and in each step remove an entry from
+			// the cache until we reach the desired cache size
+
+			let (mut new_cache, remaining_capacity) = {
+				let mut remaining_capacity = self.total_capacity;
+				//let mut est_file_capacity = self.total_capacity / self.curr_est_size;
+				let mut new_cache = BTreeMap::new();
+				for path in self.eviction_order().into_iter() {
+					let texture = &self.texture_cache[&path];
+					match texture {
+						CachedTexture::LoadRequested => {
+							new_cache.insert(path, CachedTexture::LoadRequested);
+						}
+						_ if self.is_pinned(&path) => {
+							remaining_capacity -= texture.size_estimate();
+							new_cache.insert(path, texture.clone());
+						}
+						_ => {
+							// Thew new file has to fit in the cache after this operation
+							// which is why we multiply the estimated size by two
+							if remaining_capacity > (self.curr_est_size * 2) {
+								remaining_capacity -= texture.size_estimate();
+								new_cache.insert(path, texture.clone());
+							}
+						}
+					}
+				}
+
+				(new_cache, remaining_capacity)
+			};
+
+			mem::swap(&mut self.texture_cache, &mut new_cache);
+			self.remaining_capacity = remaining_capacity;
+		}
+
+		let target_file_name = self.shared_cache_key(&target_file_name);
+		let settings = self.loader.settings();
+		let metadata = match archive::metadata(path.as_path()) {
+			Ok(metadata) => metadata,
+			Err(error) => {
+				self.retry_on_loader(path.as_path(), &target_file_name);
+				return Err(error.into());
+			}
+		};
+
+		// Check if it is inside the texture cache first
+		{
+			let texture_entry = self.texture_cache.entry(target_file_name.clone());
+			if let Entry::Occupied(mut entry) = texture_entry {
+				let cached = entry.get_mut();
+				if is_current(path.as_path(), cached, &metadata) {
+					cached.touch();
+					if let Some(texture) = cached.texture() {
+						self.last_load_source = Some(LoadSource::Cache);
+						return Ok(texture);
+					}
+				}
+			}
+		}
+
+		let decode_start = Instant::now();
+		let decoder = self.loader.decoder();
+		let (image, info) = match decoder.decode(path.as_path(), &settings) {
+			Ok(decoded) => decoded,
+			Err(error) => {
+				if let image_loader::errors::ErrorKind::Io(_) = error.kind() {
+					self.retry_on_loader(path.as_path(), &target_file_name);
+				}
+				return Err(classify_load_error(error, path.as_path(), self.report_corrupt_images));
+			}
+		};
+		self.stats.decode.record(decode_start.elapsed(), path.as_path());
+		self.make_room(image.size_estimate());
+
+		let content_hash = loaded_content_hash(path.as_path(), &settings);
+		let cached =
+			self.upload_decoded(display, path.as_path(), metadata, image, info, content_hash)?;
+		self.curr_est_size = cached.size_estimate();
+		self.remaining_capacity -= self.curr_est_size;
+		let result_texture = cached.texture().ok_or("The image has no frames")?;
+		if let Some(old_entry) = self.texture_cache.insert(target_file_name.clone(), cached) {
+			self.remaining_capacity += old_entry.size_estimate();
+		}
+		self.enforce_max_entries(&target_file_name);
+		self.last_load_source = Some(LoadSource::Decoded);
+
+		Ok(result_texture)
+	}
+
+	/// Requests a file that couldn't be read from the loader threads if the IO retries are
+	/// enabled, so that waiting between the retries doesn't block the caller
+	fn retry_on_loader(&mut self, path: &Path, cache_key: &OsStr) {
+		let requested =
+			matches!(self.texture_cache.get(cache_key), Some(CachedTexture::LoadRequested));
+		if requested || self.loader.settings().retry.retries == 0 {
+			return;
+		}
+		if self.loader.send_load_request(path.to_owned()) {
+			let requested = CachedTexture::LoadRequested;
+			if let Some(old_entry) = self.texture_cache.insert(cache_key.to_owned(), requested) {
+				self.remaining_capacity += old_entry.size_estimate();
+			}
+			self.requested_images += 1;
+		}
+	}
+
+	/// Loads several files at once, for example to compare them. The files loaded by the same
+	/// call are not evicted from the cache to make room for each other and the directory is
+	/// only listed once for the files that are in the same directory. The last file becomes
+	/// the current file.
+	pub fn load_many<P: AsRef<Path>>(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		paths: &[P],
+	) -> Result<Vec<Rc<T>>> {
+		let mut textures = Vec::with_capacity(paths.len());
+		for path in paths {
+			match self.load_texture(display, path.as_ref()) {
+				Ok(texture) => {
+					textures.push(texture);
+					let cache_key = self.current_cache_key();
+					self.pinned_keys.insert(cache_key);
+				}
+				Err(err) => {
+					self.pinned_keys.clear();
+					return Err(err);
+				}
+			}
+		}
+		self.pinned_keys.clear();
+		Ok(textures)
+	}
+
+	/// Loads the file and pins it, so that loading another file can't evict it. Loading two
+	/// files of the same directory this way keeps both of them in the cache whenever they fit
+	/// in its capacity together. The room for the new texture is made by evicting the files
+	/// that aren't pinned in the order of the eviction policy, only as many as needed. Use `unpin` to release
+	/// the texture.
+	pub fn load_pinned(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		path: &Path,
+	) -> Result<(Rc<T>, (u32, u32))> {
+		let resolved = resolve_path(path)?;
+		let was_pinned = self.pinned_paths.contains(&resolved);
+		self.pinned_paths.insert(resolved.clone());
+		// Make room up front if the size of the file is known
+		if let Ok(dimensions) = self.probe_dimensions(resolved.as_path()) {
+			let size_estimate = self.probed_size_estimate(resolved.as_path(), dimensions);
+			self.make_room(size_estimate);
+		}
+		let result = self.load_specific(display, resolved.as_path());
+		if result.is_err() && !was_pinned {
+			self.pinned_paths.remove(&resolved);
+		}
+		result
+	}
+
+	/// Protects the texture of the file from being evicted, for example while flipping back
+	/// and forth between two distant files. The pin is kept until `unpin` is called, even
+	/// while the file is not loaded. Pinned textures still take up the capacity of the cache.
+	pub fn pin(&mut self, path: &Path) -> Result<()> {
+		self.pinned_paths.insert(resolve_path(path)?);
+		Ok(())
+	}
+
+	pub fn unpin(&mut self, path: &Path) {
+		if let Ok(path) = resolve_path(path) {
+			self.pinned_paths.remove(&path);
+		}
+	}
+
+	/// Tells if the entry under `key` was pinned with `pin` or it was loaded by the ongoing
+	/// `load_many`
+	fn is_pinned(&self, key: &OsStr) -> bool {
+		self.pinned_keys.contains(key) || self.pinned_paths.contains(&self.dir_path.join(key))
+	}
+
+	/// Evicts textures that aren't pinned in the order of the eviction policy until `size`
+	/// more bytes fit in the cache, or there's nothing else to evict
+	fn make_room(&mut self, size: isize) {
+		for key in self.eviction_candidates() {
+			if self.remaining_capacity >= size {
+				break;
+			}
+			if let Some(cached) = self.texture_cache.remove(&key) {
+				self.remaining_capacity += cached.size_estimate();
+			}
+		}
+	}
+
+	/// The keys of the textures that may be evicted, starting with the one the eviction policy
+	/// evicts first. Pinned textures and requests that haven't arrived yet are left out.
+	fn eviction_candidates(&self) -> Vec<OsString> {
+		self.eviction_order()
+			.into_iter()
+			.rev()
+			.filter(|key| {
+				!self.is_pinned(key)
+					&& !matches!(self.texture_cache[key], CachedTexture::LoadRequested)
+			})
+			.collect()
+	}
+
+	/// Returns the paths of the files whose textures `load_specific` would evict to make room
+	/// for a new texture taking up `new_image_size` bytes. The cache is not changed.
+	pub fn eviction_preview(&self, new_image_size: isize) -> Vec<PathBuf> {
+		let mut evicted = BTreeSet::new();
+		let mut count = self.texture_count() + 1;
+		let mut remaining_capacity = self.remaining_capacity;
+		for key in self.eviction_candidates() {
+			if remaining_capacity >= new_image_size {
+				break;
+			}
+			remaining_capacity += self.texture_cache[&key].size_estimate();
+			count -= 1;
+			evicted.insert(key);
+		}
+		if let Some(max_entries) = self.max_entries {
+			for key in self.eviction_candidates() {
+				if count <= max_entries {
+					break;
+				}
+				if evicted.contains(&key) {
+					continue;
+				}
+				evicted.insert(key);
+				count -= 1;
+			}
+		}
+		// The entries that were not loaded from a file have no path
+		evicted
+			.into_iter()
+			.filter(|key| self.texture_cache[key].metadata().is_some())
+			.map(|key| self.dir_path.join(key))
+			.collect()
+	}
+
+	/// Returns the paths of the cached files that were modified or removed since their
+	/// textures were loaded. Nothing is loaded again.
+	pub fn stale_entries(&self) -> Vec<PathBuf> {
+		self.texture_cache
+			.iter()
+			.filter_map(|(key, cached)| {
+				cached.metadata()?;
+				let path = self.dir_path.join(key);
+				let is_stale = match archive::metadata(path.as_path()) {
+					Ok(metadata) => !is_current(path.as_path(), cached, &metadata),
+					Err(_) => true,
+				};
+				if is_stale {
+					Some(path)
+				} else {
+					None
+				}
+			})
+			.collect()
+	}
+
+	/// Returns the path of every cached file with the estimated number of bytes its texture
+	/// takes up. The files that are still being loaded and the images loaded from memory are
+	/// not included.
+	pub fn cached_entries(&self) -> Vec<(PathBuf, usize)> {
+		self.texture_cache
+			.iter()
+			.filter(|(_, cached)| cached.metadata().is_some())
+			.map(|(key, cached)| (self.dir_path.join(key), cached.size_estimate().max(0) as usize))
+			.collect()
+	}
+
+	/// Returns the keys of the cache entries ordered by the eviction policy, starting with the
+	/// entry that should be kept the longest.
+	fn eviction_order(&self) -> Vec<OsString> {
+		let mut sorted_files: Vec<_> = self.texture_cache.iter().enumerate().rev().collect();
+		match self.eviction_policy {
+			EvictionPolicy::Positional => {
+				sorted_files.sort_by_key(|(index, _)| {
+					(*index as isize - self.current_index as isize).abs()
+				});
+			}
+			EvictionPolicy::LeastRecentlyUsed => {
+				let now = Instant::now();
+				sorted_files
+					.sort_by_key(|(_, (_, texture))| Reverse(texture.last_access().unwrap_or(now)));
+			}
+		}
+		sorted_files.into_iter().map(|(_, (path, _))| path.clone()).collect()
+	}
+
+	/// Starts loading the image on one of the loader threads and returns without waiting for it.
+	///
+	/// The texture becomes available through `try_get` once `process_prefetched` received it.
+	/// Requesting a file from another directory opens that directory, the same way as
+	/// `load_specific` does. Fails with `QueueFull` if the loader threads can't take any more
+	/// requests.
+	pub fn request_specific(&mut self, path: &Path) -> Result<()> {
+		let path = resolve_path(path)?;
+		let (parent, target_file_name) = self.locate_file(path.as_path())?;
+
+		if self.dir_path != parent {
+			self.listing_deferred = false;
+			self.switch_directory(parent, Some(target_file_name.clone()))?;
+		}
+
+		let target_file_name = self.shared_cache_key(&target_file_name);
+		let metadata = archive::metadata(path.as_path())?;
+		let is_loaded = match self.texture_cache.get(&target_file_name) {
+			Some(CachedTexture::LoadRequested) => true,
+			Some(cached) => is_current(path.as_path(), cached, &metadata),
+			None => false,
+		};
+		if !is_loaded {
+			if !self.loader.send_load_request(path) {
+				bail!(ErrorKind::QueueFull);
+			}
+			self.texture_cache.entry(target_file_name).or_insert(CachedTexture::LoadRequested);
+			self.requested_images += 1;
+		}
+		Ok(())
+	}
+
+	/// Same as `load_specific` but the file is decoded on one of the loader threads and this
+	/// fails with `Timeout` if that takes longer than `timeout`. The request is not cancelled
+	/// then, the texture is inserted into the cache once it's decoded. The decoding error of
+	/// the file is returned as soon as it arrives, the results of the other files are
+	/// processed like by `process_prefetched` while waiting. Without loader threads the file
+	/// is loaded on the calling thread without a time limit.
+	pub fn load_specific_timeout(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		path: &Path,
+		timeout: Duration,
+	) -> Result<(Rc<T>, (u32, u32))> {
+		if self.loader.thread_count() == 0 {
+			return self.load_specific(display, path);
+		}
+		let start = Instant::now();
+		self.request_specific(path)?;
+
+		let path = resolve_path(path)?;
+		let (_, file_name) = self.locate_file(path.as_path())?;
+		let cache_key = self.cache_key(&file_name);
+		let metadata = archive::metadata(path.as_path())?;
+		loop {
+			// An outdated texture stays in the cache until the new one replaces it
+			let is_loaded = match self.texture_cache.get(&cache_key) {
+				Some(cached) => is_current(path.as_path(), cached, &metadata),
+				None => false,
+			};
+			if is_loaded {
+				// Finds the texture in the cache and makes the file the current one
+				return self.load_specific(display, path.as_path());
+			}
+			let remaining = match timeout.checked_sub(start.elapsed()) {
+				Some(remaining) => remaining,
+				None => bail!(ErrorKind::Timeout),
+			};
+			match self.loader.recv_prefetched_timeout(remaining) {
+				Ok(LoadResult::Failed { path: failed_path, error, generation })
+					if failed_path == path =>
+				{
+					self.requested_images -= 1;
+					// Only a timeout leaves the request pending
+					self.forget_request(path.as_path(), generation);
+					bail!(classify_load_error(error, path.as_path(), self.report_corrupt_images));
+				}
+				Ok(load_result) => {
+					self.receive_load_result(display, load_result)?;
+				}
+				Err(RecvTimeoutError::Timeout) => bail!(ErrorKind::Timeout),
+				Err(RecvTimeoutError::Disconnected) => bail!(ErrorKind::LoaderDisconnected),
+			}
+		}
+	}
+
+	/// Returns the texture of the file if it is in the cache. This never loads the file.
+	pub fn try_get(&mut self, path: &Path) -> Option<Rc<T>> {
+		let path = archive::canonicalize(path).ok()?;
+		let (parent, file_name) = self.locate_file(path.as_path()).ok()?;
+		if parent != self.dir_path {
+			return None;
+		}
+		let cached = self.texture_cache.get_mut(&self.cache_key(&file_name))?;
+		cached.touch();
+		cached.texture()
+	}
+
+	/// Removes the texture of the file from the cache, for example after the file was deleted
+	/// or moved. Does nothing if the file isn't cached.
+	pub fn evict(&mut self, path: &Path) {
+		let path = match resolve_path(path) {
+			Ok(path) => path,
+			Err(_) => return,
+		};
+		let file_name = match self.locate_file(path.as_path()) {
+			Ok((parent, file_name)) if parent == self.dir_path => file_name,
+			_ => return,
+		};
+		if let Some(cached) = self.texture_cache.remove(&self.cache_key(&file_name)) {
+			self.remaining_capacity += cached.size_estimate();
+		}
+	}
+
+	/// Decodes an image that is not stored in a file and inserts it into the cache under `key`.
+	///
+	/// These entries are evicted from the cache the same way as the ones loaded from files.
+	pub fn load_from_bytes(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		key: &str,
+		bytes: &[u8],
+	) -> Result<Rc<T>> {
+		self.select_display(display);
+		let image = self.loader.decoder().decode_bytes(bytes, &self.loader.settings())?;
+		let cache_key = memory_cache_key(key);
+
+		if let Some(CachedTexture::InMemory(old_entry)) = self.texture_cache.remove(&cache_key) {
+			self.remaining_capacity += old_entry.size_estimate();
+		}
+
+		self.make_room(get_image_size_estimate(image.dimensions()) as isize);
+
+		let sampling = SamplingHint::for_image(&image);
+		let result_texture = Rc::new(display.upload(image)?);
+		let texture_entry = TextureEntry { sampling, ..TextureEntry::new(result_texture.clone()) };
+		self.curr_est_size = texture_entry.size_estimate();
+		self.remaining_capacity -= self.curr_est_size;
+		self.texture_cache.insert(cache_key.clone(), CachedTexture::InMemory(texture_entry));
+		self.enforce_max_entries(&cache_key);
+		self.last_load_source = Some(LoadSource::Decoded);
+
+		Ok(result_texture)
+	}
+
+	/// Returns a texture of the image that fits in a `max_size` by `max_size` square.
+	///
+	/// Thumbnails are decoded on the calling thread and are kept in a cache of their own so
+	/// that they don't take up the capacity of the full sized textures. The least recently
+	/// used thumbnails are evicted when the thumbnail capacity is exceeded.
+	pub fn load_thumbnail(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		path: &Path,
+		max_size: u32,
+	) -> Result<Rc<T>> {
+		self.load_thumbnail_with_mode(display, path, max_size, ThumbnailMode::Fit)
+	}
+
+	/// Same as `load_thumbnail` but `SquareCrop` makes every thumbnail exactly `size` by
+	/// `size`, for example to display them in a uniform grid. The thumbnails of the two modes
+	/// are cached separately.
+	pub fn load_thumbnail_with_mode(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		path: &Path,
+		size: u32,
+		mode: ThumbnailMode,
+	) -> Result<Rc<T>> {
+		self.select_display(display);
+		let path = archive::canonicalize(path)?;
+		let metadata = archive::metadata(path.as_path())?;
+		let key = (path, size, mode);
+
+		if let Some((cached_metadata, entry)) = self.thumbnail_cache.get_mut(&key) {
+			if !is_modified(cached_metadata, &metadata) {
+				entry.last_access = Instant::now();
+				return Ok(entry.texture.clone());
+			}
+		}
+		if let Some((_, old_entry)) = self.thumbnail_cache.remove(&key) {
+			self.thumbnail_remaining_capacity += old_entry.size_estimate();
+		}
+
+		let settings = self.loader.settings();
+		let decoder = self.loader.decoder();
+		let image = match mode {
+			ThumbnailMode::Fit => decoder.thumbnail(key.0.as_path(), size, &settings)?,
+			ThumbnailMode::SquareCrop => {
+				decoder.square_thumbnail(key.0.as_path(), size, &settings)?
+			}
+		};
+		let size_estimate = get_image_size_estimate(image.dimensions()) as isize;
+		while self.thumbnail_remaining_capacity < size_estimate {
+			let oldest = self
+				.thumbnail_cache
+				.iter()
+				.min_by_key(|(_, (_, entry))| entry.last_access)
+				.map(|(key, _)| key.clone());
+			match oldest.and_then(|oldest| self.thumbnail_cache.remove(&oldest)) {
+				Some((_, entry)) => self.thumbnail_remaining_capacity += entry.size_estimate(),
+				None => break,
+			}
+		}
+
+		let texture = Rc::new(display.upload(image)?);
+		let entry = TextureEntry::new(texture.clone());
+		self.thumbnail_remaining_capacity -= entry.size_estimate();
+		self.thumbnail_cache.insert(key, (metadata, entry));
+		Ok(texture)
+	}
+
+	/// Returns the width and height of the image in the file by reading only its header. The
+	/// result is cached until the file is modified and it's also used to estimate the size of
+	/// the texture when the file is prefetched.
+	pub fn probe_dimensions(&mut self, path: &Path) -> Result<(u32, u32)> {
+		let path = archive::canonicalize(path)?;
+		let metadata = archive::metadata(path.as_path())?;
+		if let Some((cached_metadata, dimensions)) = self.dimension_cache.get(&path) {
+			if !is_modified(cached_metadata, &metadata) {
+				return Ok(*dimensions);
+			}
+		}
+		let dimensions = self.loader.decoder().dimensions(path.as_path())?;
+		self.dimension_cache.insert(path, (metadata, dimensions));
+		Ok(dimensions)
+	}
+
+	/// The directory that the current file is in. This is empty before the first file is
+	/// opened.
+	pub fn directory(&self) -> &Path {
+		self.dir_path.as_path()
+	}
+
+	/// Opens the directory (or archive) and makes its first file the current one, without
+	/// loading anything. The listing is refreshed if the directory is already open.
+	pub fn set_directory(&mut self, path: &Path) -> Result<()> {
+		let dir_path = resolve_path(path)?;
+		if !dir_path.is_dir() && !archive::is_archive(dir_path.as_path()) {
+			bail!(format!("'{}' is not a directory", dir_path.to_string_lossy()));
+		}
+		self.listing_deferred = false;
+		if self.dir_path != dir_path {
+			self.switch_directory(dir_path, None)?;
+		} else {
+			self.update_directory()?;
+			self.current_index = 0;
+		}
+		self.current_page = 0;
+		Ok(())
+	}
+
+	/// Opens the directory and starts loading its files on the loader threads, as many as fit
+	/// in the cache. This doesn't need a display, the textures are created by the following
+	/// `process_prefetched` calls. The current file stays the same if the directory is already
+	/// open, otherwise the first file of the directory becomes the current one.
+	pub fn prefetch_directory(&mut self, path: &Path) -> Result<()> {
+		use std::collections::btree_map::Entry;
+
+		let dir_path = resolve_path(path)?;
+		if self.dir_path != dir_path {
+			self.switch_directory(dir_path, None)?;
+		} else {
+			self.update_directory()?;
+		}
+
+		let mut remaining_capacity = self.remaining_capacity;
+		for index in 0..self.dir_files.len() {
+			let path = self.dir_files[index].path.clone();
+			let size_estimate = match self.dimension_cache.get(&path) {
+				Some((_, dimensions)) => self.probed_size_estimate(path.as_path(), *dimensions),
+				None => self.curr_est_size,
+			};
+			// The first file is requested even if its estimated size is too large
+			if remaining_capacity < size_estimate && remaining_capacity < self.remaining_capacity {
+				break;
+			}
+			let cache_key = self.resolve_cache_key(index);
+			if let Entry::Vacant(entry) = self.texture_cache.entry(cache_key) {
+				if !self.loader.send_load_request(path) {
+					// The rest of the files would be refused as well
+					break;
+				}
+				entry.insert(CachedTexture::LoadRequested);
+				self.requested_images += 1;
+				remaining_capacity -= size_estimate;
+			}
+		}
+		Ok(())
+	}
+
+	/// Loads the textures of all the files in the current directory into the cache.
+	///
+	/// After each file `progress` is called with the number of files processed so far and the
+	/// total number of files. Loading stops if `progress` returns `false` or when there's no
+	/// more room in the cache. Files that can't be loaded are skipped.
+	pub fn preload_all<F>(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		mut progress: F,
+	) -> Result<()>
+	where
+		F: FnMut(usize, usize) -> bool,
+	{
+		let settings = self.loader.settings();
+		let decoder = self.loader.decoder();
+		let total = self.dir_files.len();
+		for index in 0..total {
+			let name = self.resolve_cache_key(index);
+			let path = self.dir_files[index].path.clone();
+
+			if let Ok(metadata) = archive::metadata(path.as_path()) {
+				let cached = self.texture_cache.get(&name);
+				let is_cached = match cached {
+					Some(cached) => is_current(path.as_path(), cached, &metadata),
+					None => false,
+				};
+				if !is_cached {
+					let old_size_estimate = cached.map_or(0, |cached| cached.size_estimate());
+					let decode_start = Instant::now();
+					if let Ok((image, info)) = decoder.decode(path.as_path(), &settings) {
+						self.stats.decode.record(decode_start.elapsed(), path.as_path());
+						let size_estimate = image.size_estimate();
+						if self.remaining_capacity + old_size_estimate < size_estimate {
+							break;
+						}
+						let content_hash = loaded_content_hash(path.as_path(), &settings);
+						let texture = self.upload_decoded(
+							display,
+							path.as_path(),
+							metadata,
+							image,
+							info,
+							content_hash,
+						)?;
+						self.remaining_capacity += old_size_estimate;
+						self.remaining_capacity -= texture.size_estimate();
+						self.texture_cache.insert(name.clone(), texture);
+						if self.max_entries.is_some_and(|max| self.texture_count() >= max) {
+							self.enforce_max_entries(&name);
+							break;
+						}
+					}
+				}
+			}
+
+			if !progress(index + 1, total) {
+				break;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Steps to the next page if the current file is a multi-page document that isn't on its
+	/// last page, otherwise loads the next file. See `load_jump`.
+	pub fn load_next(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+	) -> Result<(Rc<T>, OsString, (u32, u32))> {
+		self.load_jump(display, 1)
+	}
+
+	/// Same as `load_next` but skips the files that can't be decoded, for example because
+	/// they are corrupt or in an unsupported format. Returns the names of the skipped files
+	/// along with the file that was loaded. Fails if every file of the directory was skipped,
+	/// the decoding errors are always reported as `CorruptImage` by this method. The current
+	/// file stays the same when this fails.
+	#[allow(clippy::type_complexity)]
+	pub fn load_next_skipping(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+	) -> Result<((Rc<T>, OsString, (u32, u32)), Vec<OsString>)> {
+		// The skipped files are recognized by the error
+		let report_corrupt_images = mem::replace(&mut self.report_corrupt_images, true);
+		let result = self.load_next_skipping_corrupt(display);
+		self.report_corrupt_images = report_corrupt_images;
+		result
+	}
+
+	#[allow(clippy::type_complexity)]
+	fn load_next_skipping_corrupt(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+	) -> Result<((Rc<T>, OsString, (u32, u32)), Vec<OsString>)> {
+		let (start_index, start_page) = (self.current_index, self.current_page);
+		let mut skipped = Vec::new();
+		loop {
+			match self.load_next(display) {
+				Ok(loaded) => return Ok((loaded, skipped)),
+				Err(Error(ErrorKind::CorruptImage(path), _))
+					if skipped.len() < self.dir_files.len() =>
+				{
+					// The next attempt continues from the file that couldn't be loaded. The
+					// path of the error may be spelled differently than the listed one.
+					let path = resolve_path(path.as_path()).unwrap_or(path);
+					let index = match self.locate_file(path.as_path()) {
+						Ok((parent, name)) if parent == self.dir_path => {
+							self.find_file(parent.as_path(), &name)
+						}
+						_ => None,
+					};
+					if let Some(index) = index {
+						self.current_index = index;
+					}
+					skipped.push(path.file_name().unwrap_or_else(|| OsStr::new("")).to_owned());
+				}
+				Err(err) => {
+					// Nothing was loaded, so the file that was current stays the current one
+					self.current_index = start_index;
+					self.current_page = start_page;
+					return Err(err);
+				}
+			}
+		}
+	}
+
+	/// Steps to the previous page if the current file is a multi-page document that isn't on
+	/// its first page, otherwise loads the previous file. A previous document is shown from
+	/// its last page. See `load_jump`.
+	pub fn load_prev(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+	) -> Result<(Rc<T>, OsString, (u32, u32))> {
+		self.load_jump(display, -1)
+	}
+
+	/// Returns the page `step` pages away from the current one within the current file, or
+	/// `None` if there's no such page
+	fn step_page(&mut self, step: isize) -> Option<(Rc<T>, OsString, (u32, u32))> {
+		let page = self.current_page as isize + step;
+		if page < 0 {
+			return None;
+		}
+		let cached = self.texture_cache.get_mut(&self.current_cache_key())?;
+		let texture = cached.page(page as usize)?;
+		cached.touch();
+		self.current_page = page as usize;
+		let dimensions = texture.texture_dimensions();
+		Some((texture, self.current_filename(), dimensions))
+	}
+
+	/// Advances to the next file once `interval` has passed since `last_advance`, for
+	/// slideshows. The slideshow starts over from the first file at the end of the directory,
+	/// even when wrapping around is disabled. Until then, the prefetched images are uploaded
+	/// and the files ahead are requested so that the next one is ready in time.
+	///
+	/// Returns `None` if it's not yet time to advance, otherwise `last_advance` is set to the
+	/// current time.
+	pub fn next_if_elapsed(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		interval: Duration,
+		last_advance: &mut Instant,
+	) -> Result<Option<AdvancedImage<T>>> {
+		if last_advance.elapsed() < interval {
+			match self.process_prefetched(display) {
+				// Without threads the next file is simply loaded when it's time
+				Err(Error(ErrorKind::LoaderDisconnected, _)) => (),
+				result => result?,
+			}
+			self.send_load_requests();
+			return Ok(None);
+		}
+		let result = match self.load_next(display) {
+			Err(Error(ErrorKind::EndOfDirectory, _)) => self.load_first(display),
+			result => result,
+		}?;
+		*last_advance = Instant::now();
+		self.send_load_requests();
+		Ok(Some(result))
+	}
+
+	/// Loads the first file of the directory. The files after it are prefetched.
+	pub fn load_first(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+	) -> Result<(Rc<T>, OsString, (u32, u32))> {
+		let result = self.load_at_index(display, 0)?;
+		self.navigating_backward = false;
+		Ok(result)
+	}
+
+	/// Loads the last file of the directory. The files before it are prefetched.
+	pub fn load_last(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+	) -> Result<(Rc<T>, OsString, (u32, u32))> {
+		self.list_deferred_directory()?;
+		self.update_directory_if_changed()?;
+		let last_index = self.dir_files.len().saturating_sub(1);
+		let result = self.load_at_index(display, last_index)?;
+		self.navigating_backward = true;
+		Ok(result)
+	}
+
+	/// Decodes the current file again and replaces its cached texture, even if the cached
+	/// texture seems to be up to date.
+	pub fn reload_current(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+	) -> Result<(Rc<T>, (u32, u32))> {
+		if self.dir_files.is_empty() {
+			bail!(ErrorKind::EmptyDirectory);
+		}
+		if let Some(cached) = self.texture_cache.remove(&self.current_cache_key()) {
+			self.remaining_capacity += cached.size_estimate();
+		}
+		let path = self.current_file_path();
+		self.load_specific(display, &path)
+	}
+
+	/// Rotates the current file clockwise by `degrees`, which must be a multiple of 90. The
+	/// rotation adds up with the previous ones and it's kept for the file, even if its
+	/// texture is evicted from the cache and loaded again, until `reset_rotations` is called.
+	pub fn rotate_current(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		degrees: i32,
+	) -> Result<(Rc<T>, (u32, u32))> {
+		if degrees % 90 != 0 {
+			bail!(format!("Cannot rotate by {} degrees, only by multiples of 90", degrees));
+		}
+		if self.dir_files.is_empty() {
+			bail!(ErrorKind::EmptyDirectory);
+		}
+		let path = self.current_file_path();
+		let previous = self.rotations.get(&path).map_or(0, |&degrees| degrees as i32);
+		match (previous + degrees).rem_euclid(360) as u32 {
+			0 => self.rotations.remove(&path),
+			total => self.rotations.insert(path, total),
+		};
+		self.reload_current(display)
+	}
+
+	/// Mirrors the current file horizontally or vertically, as it's currently displayed. The
+	/// flip is combined with the rotation of the file and it's kept the same way.
+	pub fn flip_current(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		horizontal: bool,
+	) -> Result<(Rc<T>, (u32, u32))> {
+		if self.dir_files.is_empty() {
+			bail!(ErrorKind::EmptyDirectory);
+		}
+		let path = self.current_file_path();
+		let previous = self.rotations.get(&path).copied().unwrap_or(0);
+		match flipped_rotation(previous, horizontal) {
+			0 => self.rotations.remove(&path),
+			total => self.rotations.insert(path.clone(), total),
+		};
+		if !self.flipped.remove(&path) {
+			self.flipped.insert(path);
+		}
+		self.reload_current(display)
+	}
+
+	/// Removes the rotation and the flip of every file. The textures of the transformed files
+	/// are evicted so that they are loaded in their original orientation next time.
+	pub fn reset_rotations(&mut self) {
+		let mut paths = mem::take(&mut self.flipped);
+		paths.extend(mem::take(&mut self.rotations).into_keys());
+		for path in paths.iter() {
+			let name = match path.strip_prefix(self.dir_path.as_path()) {
+				Ok(name) => self.cache_key(name.as_os_str()),
+				Err(_) => continue,
+			};
+			if let Some(cached) = self.texture_cache.remove(&name) {
+				self.remaining_capacity += cached.size_estimate();
+			}
 		}
+	}
+
+	/// Moves `jump_count` steps forward, or backward if it's negative. Each page of the
+	/// current document is a step, the steps that go past its first or last page move between
+	/// files. The other documents count as a single step, because their pages aren't known
+	/// before they are decoded. Moving forward enters a document on its first page and moving
+	/// backward on its last one.
+	pub fn load_jump(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		jump_count: i32,
+	) -> Result<(Rc<T>, OsString, (u32, u32))> {
+		self.select_display(display);
+		let jump_count = match page_jump(self.current_page, self.current_page_count(), jump_count) {
+			PageJump::Page(step) => match self.step_page(step) {
+				Some(result) => return Ok(result),
+				None => jump_count,
+			},
+			PageJump::File(jump_count) => jump_count,
+		};
+		if jump_count != 0 {
+			self.list_deferred_directory()?;
+		}
+		self.update_directory_if_changed()?;
 
 		if self.dir_files.is_empty() {
-			return Err("Folder is empty or no folder was open when trying to load image.".into());
+			bail!(ErrorKind::EmptyDirectory);
+		}
+
+		if jump_count == 0 && archive::metadata(self.current_file_path().as_path()).is_err() {
+			// The current file was deleted, show the nearest one that is still there instead
+			self.remove_missing_files();
+			if self.dir_files.is_empty() {
+				bail!(ErrorKind::EmptyDirectory);
+			}
+			let path = self.current_file_path();
+			let (result, dimensions) = self.load_specific(display, &path)?;
+			return Ok((result, self.current_filename(), dimensions));
+		}
+
+		if jump_count == 0 {
+			let filename = self.current_filename();
+			self.last_load_source = Some(LoadSource::Cache);
+			let current_page = self.current_page;
+			let cached = self.texture_cache.get_mut(&self.current_cache_key()).and_then(|cached| {
+				cached.touch();
+				cached.page(current_page)
+			});
+			let texture = match cached {
+				Some(texture) => texture,
+				None => {
+					// The file at the current index changed, for example because the current
+					// file was renamed and the directory was listed again
+					let path = self.current_file_path();
+					let (texture, dimensions) = self.load_specific(display, &path)?;
+					return Ok((texture, self.current_filename(), dimensions));
+				}
+			};
+			let dimensions = texture.texture_dimensions();
+			return Ok((texture, filename, dimensions));
+		}
+
+		let mut target_index = self.jump_target_index(jump_count);
+		if target_index == self.current_index && !self.wrap_around {
+			bail!(ErrorKind::EndOfDirectory);
+		}
+		let mut target_path = self.dir_files[target_index].path.clone();
+		if archive::metadata(target_path.as_path()).is_err() {
+			self.remove_missing_files();
+			if self.dir_files.is_empty() {
+				bail!(ErrorKind::EmptyDirectory);
+			}
+			target_index = self.jump_target_index(jump_count);
+			target_path = self.dir_files[target_index].path.clone();
+		}
+
+		let (result, dimensions) = self.load_specific(display, &target_path)?;
+		self.current_index = target_index;
+		self.navigating_backward = jump_count < 0;
+		let last_page = self.current_page_count().saturating_sub(1);
+		if jump_count < 0 && last_page > 0 {
+			if let Some(result) = self.step_page(last_page as isize) {
+				return Ok(result);
+			}
 		}
 
-		let mut target_index =
-			(self.current_index as isize + jump_count as isize) % self.dir_files.len() as isize;
+		let filename = target_path.file_name().unwrap_or_else(|| OsStr::new("")).to_owned();
+		Ok((result, filename, dimensions))
+	}
+
+	/// The index of the file `jump_count` steps away from the current one, wrapping around
+	/// at both ends of the directory or stopping at the first and the last file if wrapping
+	/// around is disabled. The directory must not be empty.
+	fn jump_target_index(&self, jump_count: i32) -> usize {
+		let len = self.dir_files.len() as isize;
+		if !self.wrap_around {
+			let target_index = self.current_index as isize + jump_count as isize;
+			return target_index.clamp(0, len - 1) as usize;
+		}
+		let mut target_index = (self.current_index as isize + jump_count as isize) % len;
 		if target_index < 0 {
-			target_index += self.dir_files.len() as isize;
+			target_index += len;
+		}
+		target_index as usize
+	}
+
+	/// Removes the files that no longer exist on the disk from the directory and evicts their
+	/// textures from the cache. If the current file was removed, the file after it becomes
+	/// the current one.
+	fn remove_missing_files(&mut self) {
+		let current_index = self.current_index;
+		let mut new_current_index = 0;
+		let mut remaining_files = Vec::with_capacity(self.dir_files.len());
+		for (index, desc) in self.dir_files.drain(..).enumerate() {
+			if desc.metadata().is_ok() {
+				if index < current_index {
+					new_current_index += 1;
+				}
+				remaining_files.push(desc);
+			} else if let Some(cached) = self.texture_cache.remove(&desc.cache_key) {
+				self.remaining_capacity += cached.size_estimate();
+			}
 		}
+		self.current_index = new_current_index.min(remaining_files.len().saturating_sub(1));
+		self.set_dir_files(remaining_files);
+	}
 
-		let target_path = self.dir_files[target_index as usize].dir_entry.path();
-		let result = self.load_specific(display, &target_path)?;
-		self.current_index = target_index as usize;
+	/// Cancels the prefetch requests that haven't been processed yet and discards the results
+	/// of the ones that are already being decoded.
+	pub fn cancel_prefetching(&mut self) {
+		self.loader.cancel_pending_requests();
+		let requested: Vec<_> = self
+			.texture_cache
+			.iter()
+			.filter_map(|(name, cached)| match cached {
+				CachedTexture::LoadRequested => Some(name.clone()),
+				_ => None,
+			})
+			.collect();
+		for name in requested {
+			self.texture_cache.remove(&name);
+		}
+	}
 
-		Ok((result, target_path.file_name().unwrap_or_else(|| OsStr::new("")).to_owned()))
+	/// Drops every cached texture and thumbnail and cancels the pending prefetch requests.
+	/// The loader threads keep running so the cache can be used as before.
+	pub fn clear_cache(&mut self) {
+		self.loader.cancel_pending_requests();
+		self.invalidate_textures();
+		self.other_displays.clear();
 	}
 
-	pub fn process_prefetched(&mut self, display: &glium::Display) -> Result<()> {
-		use std::collections::btree_map::Entry;
+	/// Makes the textures of `display` the ones in the cache. The textures of the display that
+	/// was used before are put aside until that display is used again. Each display may use
+	/// the full capacity of the cache.
+	fn select_display(&mut self, display: &impl Uploader<Texture = T>) {
+		let context = match display.context() {
+			Some(context) => context,
+			None => return,
+		};
+		match &self.context {
+			Some(current) if Rc::ptr_eq(current, &context) => return,
+			Some(_) => (),
+			None => {
+				self.context = Some(context);
+				return;
+			}
+		}
+		let selected =
+			match self.other_displays.iter().position(|other| Rc::ptr_eq(&other.context, &context))
+			{
+				Some(index) => self.other_displays.swap_remove(index),
+				None => DisplayTextures {
+					context,
+					texture_cache: BTreeMap::new(),
+					thumbnail_cache: BTreeMap::new(),
+				},
+			};
+		let mut previous = DisplayTextures {
+			context: self.context.replace(selected.context).unwrap(),
+			texture_cache: mem::replace(&mut self.texture_cache, selected.texture_cache),
+			thumbnail_cache: mem::replace(&mut self.thumbnail_cache, selected.thumbnail_cache),
+		};
+
+		// The pending requests are uploaded on the display that processes them
+		let requested: Vec<_> = previous
+			.texture_cache
+			.iter()
+			.filter(|(_, cached)| cached.texture().is_none())
+			.map(|(name, _)| name.clone())
+			.collect();
+		for name in requested {
+			previous.texture_cache.remove(&name);
+			self.texture_cache.entry(name).or_insert(CachedTexture::LoadRequested);
+		}
+		self.other_displays.push(previous);
+
+		let used: isize = self.texture_cache.values().map(|cached| cached.size_estimate()).sum();
+		self.remaining_capacity = self.total_capacity - used;
+		let used: isize =
+			self.thumbnail_cache.values().map(|(_, entry)| entry.size_estimate()).sum();
+		self.thumbnail_remaining_capacity = self.thumbnail_total_capacity - used;
+	}
+
+	/// The size of the texture of the image in the file, which has these dimensions, after the
+	/// load settings are applied to it
+	fn probed_size_estimate(&self, path: &Path, dimensions: (u32, u32)) -> isize {
+		let settings = self.loader.settings();
+		let mut dimensions = dimensions;
+		if let Some(max) = settings.max_decode_dimension {
+			// Only JPEGs are decoded scaled
+			if image::ImageFormat::from_path(path).ok() == Some(image::ImageFormat::Jpeg) {
+				dimensions = scaled_jpeg_dimensions(dimensions, max);
+			}
+		}
+		if let Some(max) = settings.max_dimension {
+			dimensions = fitted_dimensions(dimensions, max);
+		}
+		get_image_size_estimate(dimensions) as isize
+	}
+
+	fn upload_decoded(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		path: &Path,
+		metadata: fs::Metadata,
+		image: DecodedImage,
+		info: Option<ImageInfo>,
+		content_hash: Option<u64>,
+	) -> Result<CachedTexture<T>> {
+		let image = if self.flipped.contains(path) { image.flipped_horizontally() } else { image };
+		let image = match self.rotations.get(path) {
+			Some(&degrees) => image.rotated(degrees),
+			None => image,
+		};
+		let start = Instant::now();
+		let mut texture = CachedTexture::from_decoded(display, metadata, image, self.keep_pixels)?;
+		texture.set_info(info);
+		texture.set_content_hash(content_hash);
+		self.stats.upload.record(start.elapsed(), path);
+		Ok(texture)
+	}
+
+	/// Uploads the images that the loader threads have decoded since the last call.
+	///
+	/// Returns `LoaderDisconnected` if all the loader threads have stopped.
+	pub fn process_prefetched(&mut self, display: &impl Uploader<Texture = T>) -> Result<()> {
+		self.process_prefetched_paths(display).map(|_| ())
+	}
+
+	/// Same as `process_prefetched` but returns the paths of the files whose textures were
+	/// inserted into the cache or replaced by this call.
+	pub fn process_prefetched_paths(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+	) -> Result<Vec<PathBuf>> {
 		use std::sync::mpsc::TryRecvError;
 
+		self.select_display(display);
+		let mut updated_paths = Vec::new();
+		let mut uploaded = 0;
 		loop {
+			// The rest of the results stay in the channel until the next call
+			if self.upload_budget.is_some_and(|budget| uploaded >= budget) {
+				break;
+			}
 			match self.loader.try_recv_prefetched() {
 				Ok(load_result) => {
-					self.requested_images -= 1;
-					if let LoadResult::Ok { path, metadata, image } = load_result {
-						let size_estimate =
-							get_image_size_estimate((image.width(), image.height())) as isize;
-						match self.texture_cache.entry(path.file_name().unwrap().to_owned()) {
-							Entry::Vacant(entry) => {
-								let texture = Rc::new(texture_from_image(display, image)?);
-								entry.insert(CachedTexture::Texture((metadata, texture)));
-								self.remaining_capacity -= size_estimate;
-							}
-							Entry::Occupied(mut entry) => match entry.get_mut() {
-								CachedTexture::Texture(ref mut entry) => {
-									if entry.0.modified().unwrap() < metadata.modified().unwrap() {
-										let old_size_estimate = {
-											let old_image = &entry.1;
-											get_image_size_estimate((
-												old_image.width(),
-												old_image.height(),
-											)) as isize
-										};
-										let texture = Rc::new(texture_from_image(display, image)?);
-										entry.0 = metadata;
-										entry.1 = texture;
-										self.remaining_capacity += old_size_estimate;
-										self.remaining_capacity -= size_estimate;
-									}
-								}
-								entry @ CachedTexture::LoadRequested => {
-									let texture = Rc::new(texture_from_image(display, image)?);
-									*entry = CachedTexture::Texture((metadata, texture));
-									self.remaining_capacity -= size_estimate;
-								}
-							},
-						}
+					if let Some(path) = self.receive_load_result(display, load_result)? {
+						uploaded += 1;
+						updated_paths.push(path);
 					}
 				}
-				Err(TryRecvError::Disconnected) => return Ok(()),
+				Err(TryRecvError::Disconnected) => bail!(ErrorKind::LoaderDisconnected),
 				Err(TryRecvError::Empty) => break,
 			}
 		}
 
-		Ok(())
+		Ok(updated_paths)
+	}
+
+	/// Inserts the texture of a decoded file into the cache and reports the decoding errors to
+	/// the prefetch error callback. Returns the path of the file if its texture was inserted
+	/// or replaced.
+	fn receive_load_result(
+		&mut self,
+		display: &impl Uploader<Texture = T>,
+		load_result: LoadResult,
+	) -> Result<Option<PathBuf>> {
+		self.requested_images -= 1;
+		let (path, metadata, image, info, content_hash) = match load_result {
+			LoadResult::Ok {
+				path,
+				metadata,
+				image,
+				info,
+				content_hash,
+				generation,
+				decode_time,
+			} => {
+				self.stats.decode.record(decode_time, path.as_path());
+				// The request was made before the pending requests were cancelled
+				if generation != self.loader.generation() {
+					return Ok(None);
+				}
+				(path, metadata, image, info, content_hash)
+			}
+			LoadResult::Failed { path, error, generation } => {
+				self.receive_failed_load(path.as_path(), error, generation);
+				return Ok(None);
+			}
+			LoadResult::Cancelled { path, generation } => {
+				self.forget_request(path.as_path(), generation);
+				return Ok(None);
+			}
+		};
+		let cache_key = self.loaded_cache_key(path.as_path());
+		let is_newer = match self.texture_cache.get(&cache_key) {
+			Some(cached) => match cached.metadata() {
+				Some(cached_metadata) => match (cached_metadata.modified(), metadata.modified()) {
+					// An edit that kept the modification time is only recognized by the hash
+					(Ok(cached_time), Ok(loaded)) if cached_time == loaded => {
+						content_hash.is_some()
+							&& cached.content_hash().is_some()
+							&& content_hash != cached.content_hash()
+					}
+					(Ok(cached), Ok(loaded)) => cached < loaded,
+					_ => is_modified(cached_metadata, &metadata),
+				},
+				None => true,
+			},
+			None => true,
+		};
+		if !is_newer {
+			return Ok(None);
+		}
+		let texture =
+			self.upload_decoded(display, path.as_path(), metadata, image, info, content_hash)?;
+		self.remaining_capacity -= texture.size_estimate();
+		match self.texture_cache.insert(cache_key.clone(), texture) {
+			Some(CachedTexture::LoadRequested) => {
+				if let Some(ref mut callback) = self.prefetch_callback {
+					callback(path.as_path());
+				}
+			}
+			Some(old_entry) => {
+				self.remaining_capacity += old_entry.size_estimate();
+			}
+			None => {}
+		}
+		self.enforce_max_entries(&cache_key);
+		Ok(Some(path))
+	}
+
+	/// Reports a file that the threads couldn't load to the error callback
+	fn receive_failed_load(
+		&mut self,
+		path: &Path,
+		error: image_loader::errors::Error,
+		generation: usize,
+	) {
+		self.forget_request(path, generation);
+		let report_corrupt = self.report_corrupt_images;
+		if let Some(ref mut callback) = self.prefetch_error_callback {
+			let error = classify_load_error(error, path, report_corrupt);
+			callback(path, &error);
+		}
+	}
+
+	/// The key of the texture cache that a file loaded by the threads is stored under
+	fn loaded_cache_key(&self, path: &Path) -> OsString {
+		match path.strip_prefix(self.dir_path.as_path()) {
+			Ok(relative) => self.cache_key(relative.as_os_str()),
+			Err(_) => path.file_name().unwrap().to_owned(),
+		}
+	}
+
+	/// Removes the placeholder of a request that didn't produce a texture, so that the file
+	/// can be requested again. Cancelling the requests already removes the placeholders, so
+	/// the placeholder of a request from before the last cancellation belongs to a newer
+	/// request and is kept.
+	fn forget_request(&mut self, path: &Path, generation: usize) {
+		if generation != self.loader.generation() {
+			return;
+		}
+		let cache_key = self.loaded_cache_key(path);
+		if let Some(CachedTexture::LoadRequested) = self.texture_cache.get(&cache_key) {
+			self.texture_cache.remove(&cache_key);
+		}
 	}
 
-	pub fn prefetch_neighbors(&mut self) {
-		let mut index = self.current_index;
+	/// Requests the files around the current one, both ahead of and behind it. Two out of three
+	/// requests go in the direction of the last navigation.
+	pub fn send_load_requests(&mut self) {
+		let direction: isize = if self.navigating_backward { -1 } else { 1 };
+		let mut ahead = 0;
+		let mut behind = 0;
+		let mut ahead_done = false;
+		let mut behind_done = false;
 
 		// Send as many load requests so that the estimated total will just fill the cache
 		let mut estimated_remaining_cap = self.remaining_capacity;
 
-		while estimated_remaining_cap > self.curr_est_size as isize {
-			if self.requested_images >= Self::MAX_PENDING_PREFETCH_REQUESTS {
+		let mut step = 0;
+		while estimated_remaining_cap > self.curr_est_size && !(ahead_done && behind_done) {
+			if self.requested_images >= self.max_pending_prefetch_requests {
 				break;
 			}
 			// Send a load request for the closest file not in the cache or outdated
-			index += 1;
-			if self.prefetch_at_index(index) {
-				estimated_remaining_cap -= self.curr_est_size as isize;
+			let go_behind = ahead_done || (step % 3 == 2 && !behind_done);
+			step += 1;
+			let offset = if go_behind {
+				behind += 1;
+				-direction * behind
+			} else {
+				ahead += 1;
+				direction * ahead
+			};
+			let index = self.current_index as isize + offset;
+			if index >= 0 && self.prefetch_at_index(index as usize) {
+				estimated_remaining_cap -= self.curr_est_size;
+			} else if go_behind {
+				behind_done = true;
 			} else {
+				ahead_done = true;
+			}
+		}
+	}
+
+	/// Requests the `count` files before and the `count` files after the current one that
+	/// aren't in the cache yet, regardless of the capacity of the cache and the limit on the
+	/// pending requests. The neighbors wrap around at the ends of the directory the same way
+	/// as `load_next` and `load_prev` do. Returns the number of files that were requested.
+	pub fn prefetch_neighbors(&mut self, count: usize) -> usize {
+		let mut requested = 0;
+		for index in self.neighbor_indices(count) {
+			let cache_key = self.resolve_cache_key(index);
+			if self.texture_cache.contains_key(&cache_key) {
+				continue;
+			}
+			if !self.loader.send_load_request(self.dir_files[index].path.clone()) {
 				break;
 			}
+			self.texture_cache.insert(cache_key, CachedTexture::LoadRequested);
+			self.requested_images += 1;
+			requested += 1;
+		}
+		requested
+	}
+
+	/// The indices of the `count` files on both sides of the current one, the closest ones first
+	fn neighbor_indices(&self, count: usize) -> Vec<usize> {
+		let count = count.min(self.dir_files.len()) as i32;
+		let mut indices = Vec::new();
+		for offset in (1..=count).flat_map(|offset| [offset, -offset]) {
+			let index = self.jump_target_index(offset);
+			// Without wrapping around the jumps past the ends stop at the first or last file
+			if index != self.current_index && !indices.contains(&index) {
+				indices.push(index);
+			}
 		}
+		indices
 	}
 
 	pub fn prefetch_at_index(&mut self, index: usize) -> bool {
 		use std::collections::btree_map::Entry;
 
-		if self.requested_images >= Self::MAX_PENDING_PREFETCH_REQUESTS {
+		if self.requested_images >= self.max_pending_prefetch_requests {
 			return false;
 		}
 
-		if self.remaining_capacity > self.curr_est_size as isize {
+		// Use the exact size if the dimensions of the file were probed
+		let size_estimate = match self.dir_files.get(index) {
+			Some(desc) => match self.dimension_cache.get(&desc.path) {
+				Some((_, dimensions)) => {
+					self.probed_size_estimate(desc.path.as_path(), *dimensions)
+				}
+				None => self.curr_est_size,
+			},
+			None => self.curr_est_size,
+		};
+		if self.remaining_capacity > size_estimate && index < self.dir_files.len() {
+			let cache_key = self.resolve_cache_key(index);
 			if let Some(desc) = self.dir_files.get(index) {
-				let file = &desc.dir_entry;
-				let file_path = file.path();
-				let file_name = if let Some(file_name) = file_path.file_name() {
-					file_name.to_owned()
-				} else {
-					return false;
-				};
-				match self.texture_cache.entry(file_name) {
+				let file_path = desc.path.clone();
+				// Only the supported files are listed, so there's no need to check it again
+				match self.texture_cache.entry(cache_key) {
 					Entry::Vacant(entry) => {
-						if is_file_supported(file_path.as_ref()) {
-							entry.insert(CachedTexture::LoadRequested);
-							self.loader.send_load_request(file_path);
-							self.requested_images += 1;
-							return true;
+						if !self.loader.send_load_request(file_path) {
+							return false;
 						}
+						entry.insert(CachedTexture::LoadRequested);
+						self.requested_images += 1;
+						return true;
 					}
 					Entry::Occupied(entry) => {
-						if let CachedTexture::Texture(ref entry) = entry.get() {
-							if entry.0.modified().unwrap()
-								!= file.metadata().unwrap().modified().unwrap()
-							{
-								self.loader.send_load_request(file_path);
+						if entry.get().metadata().is_some() {
+							// If the metadata can't be queried, just skip the file
+							let is_outdated = match desc.metadata() {
+								Ok(metadata) => {
+									!is_current(file_path.as_path(), entry.get(), &metadata)
+								}
+								Err(_) => false,
+							};
+							if is_outdated {
+								if !self.loader.send_load_request(file_path) {
+									return false;
+								}
 								self.requested_images += 1;
 							}
 						}
@@ -436,51 +2767,953 @@ impl ImageCache {
 		false
 	}
 
-	fn change_directory(&mut self, dir_path: PathBuf, filename: OsString) -> Result<()> {
-		self.dir_files = Self::collect_directory(dir_path.as_path())?;
+	/// Opens another directory. The textures of the files that are also in the new directory
+	/// (for example when moving to the parent directory in recursive mode) are kept in the
+	/// cache, everything else is removed from it. `filename` becomes the current file, or the
+	/// first file of the directory if it's `None`.
+	fn switch_directory(&mut self, dir_path: PathBuf, filename: Option<OsString>) -> Result<()> {
+		// None of the requested images are needed in the new directory
+		self.loader.cancel_pending_requests();
+		let old_dir_path = self.dir_path.clone();
+		let old_cache = mem::take(&mut self.texture_cache);
+		self.identity_keys.clear();
+		self.remaining_capacity = self.total_capacity;
+		self.change_directory(dir_path, filename)?;
+
+		let names: BTreeSet<_> = self.dir_files.iter().map(|desc| desc.name.clone()).collect();
+		for (old_name, cached) in old_cache.into_iter() {
+			if cached.metadata().is_none() {
+				continue;
+			}
+			let path = old_dir_path.join(&old_name);
+			if let Ok(name) = path.strip_prefix(self.dir_path.as_path()) {
+				let name = name.as_os_str().to_owned();
+				if names.contains(&name) {
+					let cache_key = self.cache_key(&name);
+					if !self.texture_cache.contains_key(&cache_key) {
+						self.remaining_capacity -= cached.size_estimate();
+						self.texture_cache.insert(cache_key, cached);
+					}
+				}
+			}
+		}
+		Ok(())
+	}
 
-		// Look up the index of the filename in the directory
-		for (index, desc) in self.dir_files.iter().enumerate() {
-			if desc.dir_entry.file_name() == filename {
-				self.current_index = index;
-				self.dir_path = dir_path;
-				return Ok(());
+	fn change_directory(&mut self, dir_path: PathBuf, filename: Option<OsString>) -> Result<()> {
+		self.pending_listing = None;
+		self.opened_hidden = match filename {
+			Some(ref filename) => is_hidden(dir_path.join(filename).as_path()),
+			None => false,
+		};
+		let filename = match filename {
+			Some(filename) if self.listing_deferred => {
+				return self.list_only_file(dir_path, filename);
 			}
+			Some(filename) if self.can_list_incrementally(dir_path.as_path()) => {
+				return self.start_incremental_listing(dir_path, filename);
+			}
+			filename => filename,
+		};
+		let dir_files = self.collect_directory(dir_path.as_path())?;
+		self.set_dir_files(dir_files);
+
+		// Look up the index of the filename in the directory
+		self.current_index = match filename {
+			Some(filename) => self.find_file(dir_path.as_path(), &filename).ok_or_else(|| {
+				format!(
+					"Could not find file '{}' in directory '{}'",
+					filename.to_str().unwrap(),
+					dir_path.to_str().unwrap()
+				)
+			})?,
+			None if !self.dir_files.is_empty() => 0,
+			None => bail!(ErrorKind::EmptyDirectory),
+		};
+		self.dir_path = dir_path;
+		if self.watch_directory {
+			self.dir_watcher = Some(DirWatcher::new(self.dir_path.as_path(), self.recursive)?);
 		}
+		Ok(())
+	}
 
-		Err(format!(
-			"Could not find file '{}' in directory '{}'",
-			filename.to_str().unwrap(),
-			dir_path.to_str().unwrap()
-		)
-		.into())
-	}
-
-	fn collect_directory(path: &Path) -> Result<Vec<ImageDescriptor>> {
-		let mut dir_files: Vec<_> = fs::read_dir(path)?
-			.filter_map(|x| match x {
-				Ok(entry) => match entry.file_type() {
-					Ok(file_type) => {
-						if file_type.is_file() {
-							if is_file_supported(entry.path().as_path()) {
-								Some(ImageDescriptor::from_entry(entry))
-							} else {
-								None
-							}
-						} else {
-							None
-						}
+	fn can_list_incrementally(&self, dir_path: &Path) -> bool {
+		self.incremental_listing && !self.recursive && !archive::is_archive(dir_path)
+	}
+
+	/// Makes the opened file the only one in the listing and starts a thread that reads the
+	/// directory. The files are added to the listing by `receive_listing`.
+	fn start_incremental_listing(&mut self, dir_path: PathBuf, filename: OsString) -> Result<()> {
+		const BATCH_SIZE: usize = 256;
+
+		let listed_path = dir_path.clone();
+		self.list_only_file(dir_path, filename)?;
+
+		let (listing_tx, listing_rx) = channel();
+		thread::spawn(move || {
+			let entries = match fs::read_dir(listed_path) {
+				Ok(entries) => entries,
+				Err(err) => {
+					let _ = listing_tx.send(Err(err));
+					return;
+				}
+			};
+			let mut batch = Vec::with_capacity(BATCH_SIZE);
+			for entry in entries.filter_map(|entry| entry.ok()) {
+				// The metadata follows symlinks, so the linked files are listed as well
+				let path = entry.path();
+				if path.metadata().is_ok_and(|metadata| metadata.is_file()) {
+					batch.push(path);
+				}
+				if batch.len() == BATCH_SIZE {
+					// The receiver is gone if another directory was opened in the meantime
+					if listing_tx.send(Ok(mem::take(&mut batch))).is_err() {
+						return;
 					}
-					Err(_) => None,
-				},
-				Err(_) => None,
+				}
+			}
+			let _ = listing_tx.send(Ok(batch));
+		});
+		self.pending_listing = Some(listing_rx);
+		Ok(())
+	}
+
+	/// Opens the directory with the file as the only one in the listing
+	fn list_only_file(&mut self, dir_path: PathBuf, filename: OsString) -> Result<()> {
+		let file_path = dir_path.join(&filename);
+		if !file_path.is_file() || !self.is_file_listed(file_path.as_path()) {
+			bail!(format!(
+				"Could not find file '{}' in directory '{}'",
+				filename.to_str().unwrap(),
+				dir_path.to_str().unwrap()
+			));
+		}
+		self.set_dir_files(vec![ImageDescriptor::new(file_path, dir_path.as_path())]);
+		self.current_index = 0;
+
+		self.dir_path = dir_path;
+		if self.watch_directory {
+			self.dir_watcher = Some(DirWatcher::new(self.dir_path.as_path(), self.recursive)?);
+		}
+		Ok(())
+	}
+
+	/// Adds the files that the listing thread found since the last call to the listing of the
+	/// directory, without waiting for the rest of them. The current file stays the current one.
+	fn receive_listing(&mut self) -> Result<()> {
+		let listing_rx = match self.pending_listing {
+			Some(ref listing_rx) => listing_rx,
+			None => return Ok(()),
+		};
+		let mut files = Vec::new();
+		loop {
+			match listing_rx.try_recv() {
+				Ok(Ok(mut batch)) => files.append(&mut batch),
+				Ok(Err(err)) => {
+					self.pending_listing = None;
+					return Err(err.into());
+				}
+				Err(TryRecvError::Empty) => break,
+				Err(TryRecvError::Disconnected) => {
+					self.pending_listing = None;
+					break;
+				}
+			}
+		}
+		if files.is_empty() {
+			return Ok(());
+		}
+
+		let curr_filename = self.current_filename();
+		let listed: BTreeSet<_> = self.dir_files.iter().map(|desc| desc.path.clone()).collect();
+		let mut dir_files = mem::take(&mut self.dir_files);
+		dir_files.extend(
+			files
+				.into_iter()
+				.filter(|path| !listed.contains(path))
+				.filter(|path| self.lists_hidden() || !is_hidden(path.as_path()))
+				.filter(|path| self.is_file_listed(path.as_path()))
+				.map(|path| ImageDescriptor::new(path, self.dir_path.as_path())),
+		);
+		self.sort_files(&mut dir_files);
+		self.set_dir_files(dir_files);
+		// The file might have been removed since it was opened
+		self.current_index = self.find_file(self.dir_path.as_path(), &curr_filename).unwrap_or(0);
+		Ok(())
+	}
+
+	fn collect_directory(&self, path: &Path) -> Result<Vec<ImageDescriptor>> {
+		let mut dir_files = Vec::new();
+		if archive::is_archive(path) {
+			// Archives are always traversed recursively
+			for name in archive::list_files(path)? {
+				let file_path = path.join(name);
+				if !self.lists_hidden() && is_hidden(file_path.as_path()) {
+					continue;
+				}
+				if self.is_file_listed(file_path.as_path()) {
+					dir_files.push(ImageDescriptor::new(file_path, path));
+				}
+			}
+			self.sort_files(&mut dir_files);
+		} else {
+			self.collect_files_into(path, path, &mut dir_files)?;
+		}
+		Ok(dir_files)
+	}
+
+	/// Replaces the listing of the directory
+	fn set_dir_files(&mut self, dir_files: Vec<ImageDescriptor>) {
+		self.file_indices.clear();
+		for (index, desc) in dir_files.iter().enumerate() {
+			let lowercase_name = desc.name.to_string_lossy().to_lowercase();
+			self.file_indices.entry(lowercase_name).or_default().push(index);
+		}
+		self.dir_files = dir_files;
+	}
+
+	/// Returns the index of the file called `name` in the listing of `dir_path`. If there's no
+	/// file with this name, a file whose name only differs in case is accepted if it's the same
+	/// file on the disk, as it happens on case insensitive file systems.
+	fn find_file(&self, dir_path: &Path, name: &OsStr) -> Option<usize> {
+		let candidates = self.file_indices.get(&name.to_string_lossy().to_lowercase())?;
+		if let Some(&index) = candidates.iter().find(|&&index| self.dir_files[index].name == name) {
+			return Some(index);
+		}
+		let identity = file_identity(dir_path.join(name).as_path())?;
+		candidates.iter().copied().find(|&index| {
+			file_identity(self.dir_files[index].path.as_path()).as_ref() == Some(&identity)
+		})
+	}
+
+	/// Appends the supported files of `dir_path` to `dir_files` and if the traversal is recursive
+	/// then the files of its subdirectories as well. The names are relative to `root`.
+	fn collect_files_into(
+		&self,
+		root: &Path,
+		dir_path: &Path,
+		dir_files: &mut Vec<ImageDescriptor>,
+	) -> Result<()> {
+		let mut files = Vec::new();
+		let mut subdirectories = Vec::new();
+		for entry in fs::read_dir(dir_path)?.filter_map(|x| x.ok()) {
+			if !self.lists_hidden() && is_hidden(entry.path().as_path()) {
+				continue;
+			}
+			match entry.file_type() {
+				Ok(file_type)
+					if file_type.is_file() && self.is_file_listed(entry.path().as_path()) =>
+				{
+					files.push(ImageDescriptor::new(entry.path(), root));
+				}
+				// Symlinks are not followed, so there can't be any cycles
+				Ok(file_type) if file_type.is_dir() && self.recursive => {
+					subdirectories.push(entry.path());
+				}
+				_ => (),
+			}
+		}
+
+		self.sort_files(&mut files);
+		dir_files.append(&mut files);
+
+		subdirectories
+			.sort_by(|a, b| alphanumeric_sort::compare_os_str(a.as_os_str(), b.as_os_str()));
+		for subdirectory in subdirectories {
+			// A subdirectory that can't be read shouldn't prevent opening the rest
+			let _ = self.collect_files_into(root, subdirectory.as_path(), dir_files);
+		}
+
+		Ok(())
+	}
+
+	/// Returns the directory that has to be open to navigate from the file at `path`, and
+	/// the name of the file relative to that directory.
+	fn locate_file(&self, path: &Path) -> Result<(PathBuf, OsString)> {
+		if let Some((archive_path, name)) = archive::split_archive_path(path) {
+			return Ok((archive_path.to_owned(), name.as_os_str().to_owned()));
+		}
+		if archive::is_archive(path) {
+			// Opening the archive itself shows its first image
+			let first = self.collect_directory(path)?.into_iter().next();
+			return match first {
+				Some(desc) => Ok((path.to_owned(), desc.name)),
+				None => bail!(ErrorKind::EmptyDirectory),
+			};
+		}
+
+		if self.recursive && !self.dir_path.as_os_str().is_empty() {
+			if let Ok(relative) = path.strip_prefix(self.dir_path.as_path()) {
+				if !relative.as_os_str().is_empty() {
+					return Ok((self.dir_path.clone(), relative.as_os_str().to_owned()));
+				}
+			}
+		}
+
+		let file_name = match path.file_name() {
+			Some(filename) => filename.to_owned(),
+			None => bail!(format!("Could not get filename from path '{}'", path.to_str().unwrap())),
+		};
+		let parent = path.parent().ok_or("Could not get parent directory")?.to_owned();
+
+		Ok((parent, file_name))
+	}
+
+	fn is_file_listed(&self, path: &Path) -> bool {
+		match self.file_filter {
+			Some(ref filter) => filter(path),
+			None => {
+				is_file_supported(path, &self.supported_extensions)
+					|| (self.sniff_extensionless
+						&& path.extension().is_none()
+						&& has_image_signature(path))
+			}
+		}
+	}
+
+	fn sort_files(&self, dir_files: &mut Vec<ImageDescriptor>) {
+		let direction = self.sort_direction;
+		if let Some(ref comparator) = self.file_comparator {
+			dir_files.sort_by(|a, b| direction.apply(comparator(&a.path, &b.path)));
+			return;
+		}
+		// Using stable sorts so that the order of equal items doesn't change between updates
+		match self.sort_order {
+			SortOrder::Natural => {
+				dir_files.sort_by(|a, b| direction.apply(Self::compare_names_natural(a, b)))
+			}
+			SortOrder::Lexicographic => {
+				dir_files.sort_by(|a, b| direction.apply(a.file_name().cmp(b.file_name())))
+			}
+			SortOrder::ModificationTime => {
+				Self::sort_files_by_metadata(dir_files, direction, |m| m.modified().ok())
+			}
+			SortOrder::Size => {
+				Self::sort_files_by_metadata(dir_files, direction, |m| Some(m.len()))
+			}
+		}
+	}
+
+	/// Queries the metadata only once for every file, instead of doing it at every comparison.
+	/// Files with the same key are sorted by name.
+	fn sort_files_by_metadata<K, F>(
+		dir_files: &mut Vec<ImageDescriptor>,
+		direction: SortDirection,
+		get_key: F,
+	) where
+		K: Ord,
+		F: Fn(&fs::Metadata) -> Option<K>,
+	{
+		let mut keyed_files: Vec<_> = dir_files
+			.drain(..)
+			.map(|desc| (desc.metadata().ok().and_then(|m| get_key(&m)), desc))
+			.collect();
+		keyed_files.sort_by(|(a_key, a), (b_key, b)| {
+			direction.apply(a_key.cmp(b_key).then_with(|| Self::compare_names_natural(a, b)))
+		});
+		dir_files.extend(keyed_files.into_iter().map(|(_, desc)| desc));
+	}
+
+	fn compare_names_natural(a: &ImageDescriptor, b: &ImageDescriptor) -> Ordering {
+		alphanumeric_sort::compare_os_str(a.file_name(), b.file_name())
+	}
+}
+
+/// The methods that only work with the textures created by glium
+impl ImageCache {
+	/// Same as `new` but the capacity of the full sized textures is given as a `CacheCapacity`
+	pub fn with_capacity(
+		display: &glium::Display,
+		capacity: CacheCapacity,
+		thumbnail_capacity: isize,
+		threads: u32,
+	) -> ImageCache {
+		ImageCache::new(capacity.resolve(display), thumbnail_capacity, threads)
+	}
+
+	/// The number of mipmap levels of the texture of the current file, including the full
+	/// resolution level, or `None` if the file hasn't been loaded yet
+	pub fn current_mipmap_levels(&self) -> Option<u32> {
+		Some(match self.current_texture()? {
+			ImageTexture::Srgb(texture) => texture.get_mipmap_levels(),
+			#[cfg(feature = "compressed")]
+			ImageTexture::Compressed(texture) => texture.get_mipmap_levels(),
+		})
+	}
+
+	/// Returns a copy of a mipmap level of the current file's texture, for example to display
+	/// a zoomed out image or a thumbnail without sampling the full resolution texture. Level 0
+	/// is the full resolution and every further level halves the size. The copy is not cached.
+	pub fn current_mipmap(
+		&self,
+		display: &glium::Display,
+		level: u32,
+	) -> Result<Rc<SrgbTexture2d>> {
+		match self.current_texture().ok_or("The current file hasn't been loaded")? {
+			ImageTexture::Srgb(texture) => Ok(Rc::new(copy_mipmap_level(display, texture, level)?)),
+			#[cfg(feature = "compressed")]
+			ImageTexture::Compressed(_) => bail!("The compressed textures have no mipmaps to copy"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::image_loader::tests::{StubDecoder, StubUploader};
+	use super::*;
+
+	/// The textures of the tests only have dimensions
+	type TestCache = ImageCache<(u32, u32)>;
+
+	/// Uploads the same way as `StubUploader`, but every instance is a display of its own
+	struct StubDisplay(Rc<()>);
+
+	impl Uploader for StubDisplay {
+		type Texture = (u32, u32);
+
+		fn upload(&self, image: image::RgbaImage) -> image_loader::errors::Result<(u32, u32)> {
+			StubUploader.upload(image)
+		}
+
+		#[cfg(feature = "compressed")]
+		fn upload_compressed(
+			&self,
+			image: &compressed::CompressedImage,
+		) -> image_loader::errors::Result<(u32, u32)> {
+			StubUploader.upload_compressed(image)
+		}
+
+		fn context(&self) -> Option<Rc<dyn Any>> {
+			Some(self.0.clone())
+		}
+	}
+
+	/// A cache without loader threads that lists `count` files, none of which exist
+	fn cache_with_files(count: usize) -> TestCache {
+		let mut cache = ImageCache::new(1 << 20, 1 << 20, 0);
+		cache.dir_path = PathBuf::from("dir");
+		let dir_files = (0..count)
+			.map(|index| {
+				let path = cache.dir_path.join(format!("{}.png", index));
+				ImageDescriptor::new(path, cache.dir_path.as_path())
 			})
 			.collect();
+		cache.set_dir_files(dir_files);
+		cache
+	}
 
-		dir_files.sort_unstable_by(|a, b| {
-			alphanumeric_sort::compare_os_str(&a.dir_entry.file_name(), &b.dir_entry.file_name())
-		});
+	/// Puts a 16 by 16 texture of the file at `index` into the cache as if it was loaded.
+	/// The textures with a higher `accessed` count as the more recently used ones. Returns the
+	/// size estimate of the texture.
+	fn insert_texture(cache: &mut TestCache, index: usize, accessed: u64) -> isize {
+		insert_named_texture(cache, format!("{}.png", index), accessed)
+	}
 
-		Ok(dir_files)
+	/// Same as `insert_texture` but the file is given by its name in the directory
+	fn insert_named_texture(
+		cache: &mut TestCache,
+		name: impl Into<OsString>,
+		accessed: u64,
+	) -> isize {
+		let metadata = fs::metadata(std::env::temp_dir()).unwrap();
+		let entry = TextureEntry {
+			last_access: Instant::now() + Duration::from_millis(accessed),
+			..TextureEntry::new(Rc::new((16, 16)))
+		};
+		let texture = CachedTexture::Texture((metadata, entry));
+		let size = texture.size_estimate();
+		cache.remaining_capacity -= size;
+		cache.texture_cache.insert(name.into(), texture);
+		size
+	}
+
+	/// The names of the files in the cache that have a texture
+	fn cached_names(cache: &TestCache) -> Vec<&str> {
+		cache
+			.texture_cache
+			.iter()
+			.filter(|(_, cached)| !matches!(cached, CachedTexture::LoadRequested))
+			.map(|(name, _)| name.to_str().unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn jumps_step_through_the_pages_before_the_files() {
+		assert_eq!(page_jump(0, 3, 1), PageJump::Page(1));
+		assert_eq!(page_jump(0, 3, 2), PageJump::Page(2));
+		assert_eq!(page_jump(0, 3, 3), PageJump::File(1));
+		assert_eq!(page_jump(2, 3, 5), PageJump::File(5));
+		assert_eq!(page_jump(2, 3, -2), PageJump::Page(-2));
+		assert_eq!(page_jump(1, 3, -2), PageJump::File(-1));
+		assert_eq!(page_jump(0, 1, 1), PageJump::File(1));
+		assert_eq!(page_jump(0, 0, -1), PageJump::File(-1));
+		assert_eq!(page_jump(1, 3, 0), PageJump::File(0));
+	}
+
+	#[test]
+	fn flips_mirror_the_rotated_image() {
+		let image = image::RgbaImage::from_fn(3, 2, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+		// Transforms the image like `upload_decoded`
+		let displayed = |flipped: bool, rotation: u32| {
+			let decoded = DecodedImage::Still(image.clone());
+			let decoded = if flipped { decoded.flipped_horizontally() } else { decoded };
+			match decoded.rotated(rotation) {
+				DecodedImage::Still(image) => image,
+				_ => unreachable!(),
+			}
+		};
+		for &flipped in &[false, true] {
+			for &rotation in &[0, 90, 180, 270] {
+				let before = displayed(flipped, rotation);
+				let after =
+					|horizontal| displayed(!flipped, flipped_rotation(rotation, horizontal));
+				assert_eq!(after(true), image::imageops::flip_horizontal(&before));
+				assert_eq!(after(false), image::imageops::flip_vertical(&before));
+			}
+		}
+	}
+
+	#[test]
+	fn capacities_are_parsed_with_units() {
+		let parse = |spec: &str| spec.parse::<CacheCapacity>().unwrap();
+		assert_eq!(parse("1000"), CacheCapacity::Bytes(1000));
+		assert_eq!(parse("2 kB"), CacheCapacity::Bytes(2000));
+		assert_eq!(parse("512MiB"), CacheCapacity::Bytes(512 * 1024 * 1024));
+		assert_eq!(parse("1.5GB"), CacheCapacity::Bytes(1_500_000_000));
+		assert_eq!(parse(" 25% "), CacheCapacity::VideoMemoryFraction(0.25));
+	}
+
+	#[test]
+	fn invalid_capacities_are_rejected() {
+		assert!("".parse::<CacheCapacity>().is_err());
+		assert!("12 parsecs".parse::<CacheCapacity>().is_err());
+		assert!("many%".parse::<CacheCapacity>().is_err());
+	}
+
+	#[test]
+	fn files_are_looked_up_by_name() {
+		let cache = cache_with_files(3);
+		let dir_path = cache.dir_path.clone();
+		assert_eq!(cache.find_file(dir_path.as_path(), OsStr::new("2.png")), Some(2));
+		// The listed files don't exist on the disk, so there's no file that differs in case only
+		assert_eq!(cache.find_file(dir_path.as_path(), OsStr::new("2.PNG")), None);
+		assert_eq!(cache.find_file(dir_path.as_path(), OsStr::new("3.png")), None);
+	}
+
+	#[test]
+	fn partial_listings_are_merged() {
+		let mut cache = cache_with_files(0);
+		let dir_path = cache.dir_path.clone();
+		cache.set_dir_files(vec![ImageDescriptor::new(dir_path.join("1.png"), &dir_path)]);
+		let (listing_tx, listing_rx) = channel();
+		cache.pending_listing = Some(listing_rx);
+
+		listing_tx.send(Ok(vec![dir_path.join("2.png"), dir_path.join("1.png")])).unwrap();
+		listing_tx.send(Ok(vec![dir_path.join("0.png")])).unwrap();
+		cache.receive_listing().unwrap();
+		assert_eq!(cache.current_dir_len(), 3);
+		assert_eq!(cache.current_file_index(), 1);
+		assert!(cache.pending_listing.is_some());
+
+		drop(listing_tx);
+		cache.receive_listing().unwrap();
+		assert!(cache.pending_listing.is_none());
+	}
+
+	#[test]
+	fn neighbors_wrap_around() {
+		let mut cache = cache_with_files(5);
+		assert_eq!(cache.neighbor_indices(2), vec![1, 4, 2, 3]);
+		// Every other file is a neighbor only once
+		assert_eq!(cache.neighbor_indices(10), vec![1, 4, 2, 3]);
+		cache.current_index = 4;
+		assert_eq!(cache.neighbor_indices(1), vec![0, 3]);
+	}
+
+	#[test]
+	fn neighbors_stop_at_the_ends_without_wrapping_around() {
+		let mut cache = cache_with_files(5);
+		cache.set_wrap_around(false);
+		assert_eq!(cache.neighbor_indices(2), vec![1, 2]);
+		cache.current_index = 4;
+		assert_eq!(cache.neighbor_indices(1), vec![3]);
+	}
+
+	#[test]
+	fn jumps_wrap_around_at_both_ends() {
+		let mut cache = cache_with_files(5);
+		cache.current_index = 3;
+		assert_eq!(cache.jump_target_index(1), 4);
+		assert_eq!(cache.jump_target_index(2), 0);
+		assert_eq!(cache.jump_target_index(-4), 4);
+		assert_eq!(cache.jump_target_index(-13), 0);
+	}
+
+	#[test]
+	fn jumps_stop_at_the_ends_without_wrapping_around() {
+		let mut cache = cache_with_files(5);
+		cache.set_wrap_around(false);
+		cache.current_index = 3;
+		assert_eq!(cache.jump_target_index(10), 4);
+		assert_eq!(cache.jump_target_index(-10), 0);
+		assert_eq!(cache.jump_target_index(-1), 2);
+	}
+
+	/// Creates a directory in the temporary directory that holds `files`, each with
+	/// contents that no decoder accepts
+	fn dir_with_corrupt_files(name: &str, files: &[&str]) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("emulsion-test-{}", name));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir(&dir).unwrap();
+		for file in files {
+			fs::write(dir.join(file), b"not an image").unwrap();
+		}
+		dir
+	}
+
+	#[test]
+	fn failed_requests_can_be_sent_again() {
+		let dir = dir_with_corrupt_files("failed-request", &["corrupt.png"]);
+		let path = dir.join("corrupt.png");
+		let mut cache: TestCache = ImageCache::new(1 << 20, 1 << 20, 1);
+		cache.request_specific(&path).unwrap();
+		assert_eq!(cache.pending_count(), 1);
+		match cache.loader.recv_prefetched_timeout(Duration::from_secs(10)) {
+			Ok(LoadResult::Failed { path, error, generation }) => {
+				cache.receive_failed_load(&path, error, generation)
+			}
+			_ => panic!("The corrupt file wasn't reported as a failure"),
+		}
+		assert_eq!(cache.pending_count(), 0);
+		cache.request_specific(&path).unwrap();
+		assert_eq!(cache.pending_count(), 1);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn failed_and_cancelled_requests_are_not_pending() {
+		let mut cache = cache_with_files(3);
+		for name in &["0.png", "1.png", "2.png"] {
+			cache.texture_cache.insert(name.into(), CachedTexture::LoadRequested);
+		}
+		assert_eq!(cache.pending_count(), 3);
+		let generation = cache.loader.generation();
+		let failed = cache.dir_path.join("0.png");
+		cache.receive_failed_load(&failed, "Can't decode".into(), generation);
+		assert_eq!(cache.pending_count(), 2);
+		// A failure from before a cancellation doesn't affect the newer requests
+		cache.loader.cancel_pending_requests();
+		let stale = cache.dir_path.join("1.png");
+		cache.receive_failed_load(&stale, "Can't decode".into(), generation);
+		assert_eq!(cache.pending_count(), 2);
+		cache.cancel_prefetching();
+		assert_eq!(cache.pending_count(), 0);
+	}
+
+	#[test]
+	fn positional_eviction_keeps_the_files_closest_to_the_current_one() {
+		let mut cache = cache_with_files(5);
+		cache.current_index = 1;
+		let mut size = 0;
+		for index in 0..5 {
+			size = insert_texture(&mut cache, index, index as u64);
+		}
+		cache.set_capacity(3 * size);
+		assert_eq!(cached_names(&cache), vec!["0.png", "1.png", "2.png"]);
+	}
+
+	#[test]
+	fn lru_eviction_keeps_the_most_recently_used_files() {
+		let mut cache = cache_with_files(5);
+		cache.set_eviction_policy(EvictionPolicy::LeastRecentlyUsed);
+		cache.current_index = 1;
+		let mut size = 0;
+		for (index, &accessed) in [3, 0, 4, 1, 2].iter().enumerate() {
+			size = insert_texture(&mut cache, index, accessed);
+		}
+		cache.set_capacity(3 * size);
+		assert_eq!(cached_names(&cache), vec!["0.png", "2.png", "4.png"]);
+	}
+
+	#[test]
+	fn making_room_follows_the_eviction_policy() {
+		for &(policy, kept) in &[
+			(EvictionPolicy::Positional, ["0.png", "1.png", "2.png", "3.png"]),
+			(EvictionPolicy::LeastRecentlyUsed, ["1.png", "2.png", "3.png", "4.png"]),
+		] {
+			let mut cache = cache_with_files(5);
+			cache.set_eviction_policy(policy);
+			let mut size = 0;
+			for index in 0..5 {
+				size = insert_texture(&mut cache, index, index as u64);
+			}
+			cache.set_capacity(5 * size);
+			cache.make_room(size);
+			assert_eq!(cached_names(&cache), kept);
+		}
+	}
+
+	#[test]
+	fn pinned_textures_stay_in_the_cache_when_they_fit() {
+		for &policy in &[EvictionPolicy::Positional, EvictionPolicy::LeastRecentlyUsed] {
+			let mut cache = cache_with_files(5);
+			cache.set_eviction_policy(policy);
+			cache.current_index = 2;
+			// The pinned files are the farthest and the least recently used ones
+			let size = insert_texture(&mut cache, 0, 0);
+			insert_texture(&mut cache, 4, 1);
+			insert_texture(&mut cache, 2, 2);
+			cache.pinned_paths.insert(cache.dir_path.join("0.png"));
+			cache.pinned_paths.insert(cache.dir_path.join("4.png"));
+			cache.set_capacity(3 * size);
+			cache.make_room(size);
+			assert_eq!(cached_names(&cache), vec!["0.png", "4.png"]);
+		}
+	}
+
+	#[cfg(feature = "svg")]
+	#[test]
+	fn changing_the_svg_dpi_drops_the_svg_dimensions() {
+		let mut cache = cache_with_files(1);
+		let metadata = fs::metadata(std::env::temp_dir()).unwrap();
+		for name in &["0.png", "1.svg"] {
+			cache.dimension_cache.insert(cache.dir_path.join(name), (metadata.clone(), (1, 1)));
+		}
+		insert_texture(&mut cache, 0, 0);
+		cache.set_svg_dpi(96.0);
+		assert_eq!(cache.dimension_cache.len(), 2);
+		cache.set_svg_dpi(192.0);
+		assert!(cache.texture_cache.is_empty());
+		let paths: Vec<_> = cache.dimension_cache.keys().cloned().collect();
+		assert_eq!(paths, vec![cache.dir_path.join("0.png")]);
+	}
+
+	/// A cache without loader threads that opens a directory of `files` in the temporary
+	/// directory. The stub decoder doesn't read the files, so any contents will do.
+	fn cache_in_dir(name: &str, files: &[&str]) -> (TestCache, PathBuf) {
+		let dir = dir_with_corrupt_files(name, files);
+		let mut cache: TestCache = ImageCache::new(1 << 20, 1 << 20, 0);
+		cache.set_decoder(Arc::new(StubDecoder));
+		cache.set_directory(&dir).unwrap();
+		(cache, dir)
+	}
+
+	/// Refreshes the listing whenever the watcher reports a change, until the directory has
+	/// `len` files or a few seconds passed
+	fn wait_for_dir_len(cache: &mut TestCache, len: usize) {
+		let start = Instant::now();
+		while cache.current_dir_len() != len && start.elapsed() < Duration::from_secs(5) {
+			thread::sleep(Duration::from_millis(20));
+			cache.update_directory_if_changed().unwrap();
+		}
+	}
+
+	#[test]
+	fn watched_directories_list_the_added_and_removed_files() {
+		let (mut cache, dir) = cache_in_dir("watched", &["0.png", "1.png"]);
+		cache.set_watch_directory(true).unwrap();
+		fs::write(dir.join("2.png"), b"").unwrap();
+		wait_for_dir_len(&mut cache, 3);
+		assert_eq!(cache.current_dir_len(), 3);
+		fs::remove_file(dir.join("0.png")).unwrap();
+		wait_for_dir_len(&mut cache, 2);
+		let names: Vec<_> = cache.file_names().cloned().collect();
+		assert_eq!(names, vec![OsString::from("1.png"), OsString::from("2.png")]);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn changing_the_capacity_only_evicts_what_no_longer_fits() {
+		let mut cache = cache_with_files(4);
+		let mut size = 0;
+		for index in 0..4 {
+			size = insert_texture(&mut cache, index, 0);
+		}
+		cache.set_capacity(8 * size);
+		assert_eq!(cached_names(&cache).len(), 4);
+		assert_eq!(cache.cache_usage(), (4 * size, 8 * size));
+		cache.set_capacity(2 * size + 1);
+		assert_eq!(cached_names(&cache), vec!["0.png", "1.png"]);
+		assert_eq!(cache.remaining_capacity, 1);
+	}
+
+	#[test]
+	fn switching_directories_keeps_the_files_that_are_still_listed() {
+		let dir = dir_with_corrupt_files("switch", &["0.png"]);
+		let sub_dir = dir.join("sub");
+		fs::create_dir(&sub_dir).unwrap();
+		fs::write(sub_dir.join("1.png"), b"").unwrap();
+		let mut cache: TestCache = ImageCache::new(1 << 20, 1 << 20, 0);
+		cache.set_recursive(true).unwrap();
+		cache.set_directory(&dir).unwrap();
+		insert_named_texture(&mut cache, "0.png", 0);
+		let size = insert_named_texture(&mut cache, Path::new("sub").join("1.png"), 0);
+		cache.set_directory(&sub_dir).unwrap();
+		assert_eq!(cached_names(&cache), vec!["1.png"]);
+		assert_eq!(cache.cache_usage().0, size);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn clearing_the_cache_keeps_the_loader_running() {
+		let dir = dir_with_corrupt_files("clear", &["0.png", "1.png"]);
+		let mut cache: TestCache = ImageCache::new(1 << 20, 1 << 20, 1);
+		cache.set_decoder(Arc::new(StubDecoder));
+		cache.set_directory(&dir).unwrap();
+		insert_texture(&mut cache, 0, 0);
+		cache.request_specific(&dir.join("1.png")).unwrap();
+		assert_eq!(cache.pending_count(), 1);
+		cache.clear_cache();
+		assert!(cache.texture_cache.is_empty());
+		assert_eq!(cache.pending_count(), 0);
+		assert_eq!(cache.cache_usage(), (0, 1 << 20));
+		cache.request_specific(&dir.join("1.png")).unwrap();
+		assert_eq!(cache.pending_count(), 1);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn hard_links_share_the_texture() {
+		let (mut cache, dir) = cache_in_dir("hard-links", &["0.png"]);
+		fs::hard_link(dir.join("0.png"), dir.join("1.png")).unwrap();
+		cache.update_directory().unwrap();
+		let (texture, _) = cache.load_specific(&StubUploader, &dir.join("0.png")).unwrap();
+		let (linked, _) = cache.load_specific(&StubUploader, &dir.join("1.png")).unwrap();
+		assert!(Rc::ptr_eq(&texture, &linked));
+		assert_eq!(cache.last_load_source(), Some(LoadSource::Cache));
+		assert_eq!(cached_names(&cache), vec!["0.png"]);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn files_loaded_together_dont_evict_each_other() {
+		let (mut cache, dir) = cache_in_dir("load-many", &["0.png", "1.png", "2.png", "3.png"]);
+		// The stub decoder makes two pages of every file
+		let size = (get_image_size_estimate((4, 2)) + get_image_size_estimate((2, 2))) as isize;
+		cache.set_capacity(2 * size);
+		let paths = [dir.join("0.png"), dir.join("3.png")];
+		let textures = cache.load_many(&StubUploader, &paths).unwrap();
+		assert_eq!(textures.len(), 2);
+		assert_eq!(cached_names(&cache), vec!["0.png", "3.png"]);
+		assert_eq!(cache.current_file_index(), 3);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn the_entry_limit_evicts_the_farthest_files() {
+		let mut cache = cache_with_files(5);
+		cache.current_index = 2;
+		let mut size = 0;
+		for index in 0..5 {
+			size = insert_texture(&mut cache, index, 0);
+		}
+		cache.set_max_entries(Some(3));
+		assert_eq!(cached_names(&cache), vec!["1.png", "2.png", "3.png"]);
+		assert_eq!(cache.cache_usage().0, 3 * size);
+		cache.set_max_entries(None);
+		assert_eq!(cached_names(&cache).len(), 3);
+	}
+
+	#[test]
+	fn evicting_a_file_refunds_its_size() {
+		let (mut cache, dir) = cache_in_dir("evict", &["0.png", "1.png", "2.png"]);
+		insert_texture(&mut cache, 0, 0);
+		let size = insert_texture(&mut cache, 1, 0);
+		cache.evict(&dir.join("0.png"));
+		assert_eq!(cached_names(&cache), vec!["1.png"]);
+		assert_eq!(cache.cache_usage().0, size);
+		// Neither the files that aren't cached nor the ones that don't exist are evicted
+		cache.evict(&dir.join("2.png"));
+		cache.evict(&dir.join("missing.png"));
+		assert_eq!(cached_names(&cache), vec!["1.png"]);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn every_display_gets_its_own_textures() {
+		let (mut cache, dir) = cache_in_dir("displays", &["0.png"]);
+		let (first, second) = (StubDisplay(Rc::new(())), StubDisplay(Rc::new(())));
+		let path = dir.join("0.png");
+		let (texture, _) = cache.load_specific(&first, &path).unwrap();
+		let (other, _) = cache.load_specific(&second, &path).unwrap();
+		assert_eq!(cache.last_load_source(), Some(LoadSource::Decoded));
+		assert!(!Rc::ptr_eq(&texture, &other));
+		let (cached, _) = cache.load_specific(&first, &path).unwrap();
+		assert_eq!(cache.last_load_source(), Some(LoadSource::Cache));
+		assert!(Rc::ptr_eq(&texture, &cached));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn pinned_files_stay_cached_until_they_are_unpinned() {
+		let (mut cache, dir) = cache_in_dir("pin", &["0.png", "1.png", "2.png", "3.png", "4.png"]);
+		let size = (get_image_size_estimate((4, 2)) + get_image_size_estimate((2, 2))) as isize;
+		cache.set_capacity(2 * size);
+		let load = |cache: &mut TestCache, name: &str| {
+			cache.load_specific(&StubUploader, &dir.join(name)).unwrap();
+		};
+		load(&mut cache, "0.png");
+		cache.pin(&dir.join("0.png")).unwrap();
+		load(&mut cache, "3.png");
+		load(&mut cache, "4.png");
+		assert_eq!(cached_names(&cache), vec!["0.png", "4.png"]);
+		cache.unpin(&dir.join("0.png"));
+		load(&mut cache, "3.png");
+		assert_eq!(cached_names(&cache), vec!["3.png"]);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn only_the_loaded_files_are_listed_as_cached_entries() {
+		let mut cache = cache_with_files(2);
+		let size = insert_texture(&mut cache, 0, 0);
+		cache.texture_cache.insert("1.png".into(), CachedTexture::LoadRequested);
+		let in_memory = CachedTexture::InMemory(TextureEntry::new(Rc::new((16, 16))));
+		cache.texture_cache.insert(memory_cache_key("clipboard"), in_memory);
+		let entries = cache.cached_entries();
+		assert_eq!(entries, vec![(cache.dir_path.join("0.png"), size as usize)]);
+	}
+
+	#[test]
+	fn removing_the_current_file_moves_to_the_one_after_it() {
+		let (mut cache, dir) = cache_in_dir("removed-current", &["0.png", "1.png", "2.png"]);
+		cache.load_specific(&StubUploader, &dir.join("1.png")).unwrap();
+		fs::remove_file(dir.join("1.png")).unwrap();
+		let (_, name, _) = cache.load_jump(&StubUploader, 0).unwrap();
+		assert_eq!(name, OsString::from("2.png"));
+		assert_eq!(cache.current_dir_len(), 2);
+		let (_, name, _) = cache.load_jump(&StubUploader, -1).unwrap();
+		assert_eq!(name, OsString::from("0.png"));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn setting_the_directory_starts_at_its_first_file() {
+		let (mut cache, dir) = cache_in_dir("set-directory", &["0.png", "1.png"]);
+		assert_eq!(cache.directory(), fs::canonicalize(&dir).unwrap());
+		assert_eq!(cache.current_file_index(), 0);
+		cache.load_specific(&StubUploader, &dir.join("1.png")).unwrap();
+		assert_eq!(cache.current_file_index(), 1);
+		cache.set_directory(&dir).unwrap();
+		assert_eq!(cache.current_file_index(), 0);
+		assert!(cache.set_directory(&dir.join("0.png")).is_err());
+		assert_eq!(cache.directory(), fs::canonicalize(&dir).unwrap());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn skipped_files_are_reported_by_name() {
+		let dir = dir_with_corrupt_files("skipping", &["1.png", "2.png"]);
+		for name in &["0.png", "3.png"] {
+			image::RgbaImage::new(2, 2).save(dir.join(name)).unwrap();
+		}
+		let mut cache: TestCache = ImageCache::new(1 << 20, 1 << 20, 0);
+		cache.load_specific(&StubUploader, &dir.join("0.png")).unwrap();
+		let ((_, name, _), skipped) = cache.load_next_skipping(&StubUploader).unwrap();
+		assert_eq!(name, OsString::from("3.png"));
+		assert_eq!(skipped, vec![OsString::from("1.png"), OsString::from("2.png")]);
+		assert_eq!(cache.current_file_index(), 3);
+		fs::remove_dir_all(&dir).unwrap();
 	}
 }
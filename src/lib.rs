@@ -0,0 +1,7 @@
+//! The image cache of emulsion. It's a library of its own so that the parts of its interface
+//! that the viewer doesn't use yet are still available to other programs.
+
+#[macro_use]
+extern crate error_chain;
+
+pub mod image_cache;
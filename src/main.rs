@@ -1,8 +1,5 @@
 #![windows_subsystem = "windows"]
 
-#[macro_use]
-extern crate error_chain;
-
 use std::cell::{Cell, RefCell};
 use std::f32;
 use std::rc::Rc;
@@ -39,7 +36,6 @@ use crate::picture_widget::*;
 mod configuration;
 mod handle_panic;
 mod help_screen;
-mod image_cache;
 mod picture_widget;
 mod playback_manager;
 mod shaders;
@@ -53,7 +49,7 @@ fn main() {
 	let exe_path = std::env::current_exe().unwrap();
 	let exe_folder = exe_path.parent().unwrap();
 	let img = image::load_from_memory(include_bytes!("../resource/emulsion48.png")).unwrap();
-	let rgba = img.into_rgba();
+	let rgba = img.into_rgba8();
 	let (w, h) = rgba.dimensions();
 	let icon = Icon::from_rgba(rgba.into_raw(), w, h).unwrap();
 
@@ -5,10 +5,14 @@ use std::rc::{Rc, Weak};
 use crate::shaders;
 
 use crate::playback_manager::*;
+use emulsion::image_cache::ImageTexture;
 
 use gelatin::cgmath::{Matrix4, Vector3};
 use gelatin::glium::glutin::event::{ElementState, MouseButton};
-use gelatin::glium::{program, texture::SrgbTexture2d, uniform, Display, Frame, Program, Surface};
+use gelatin::glium::uniforms::{
+	AsUniformValue, MagnifySamplerFilter, Sampler, SamplerBehavior, SamplerWrapFunction,
+};
+use gelatin::glium::{program, uniform, Display, DrawParameters, Frame, Program, Surface};
 
 use gelatin::add_common_widget_functions;
 use gelatin::line_layout_container::HorizontalLayoutContainer;
@@ -122,9 +126,34 @@ impl PictureWidgetData {
 		display.gl_window().window().set_title(title.as_ref());
 	}
 
-	fn get_texture(&self) -> Option<Rc<SrgbTexture2d>> {
+	fn get_texture(&self) -> Option<Rc<ImageTexture>> {
 		self.playback_manager.image_texture().clone()
 	}
+
+	/// Draws the unit quad transformed by `matrix`, with the sampled texture stretched over it
+	fn draw_image<S: AsUniformValue>(
+		&self,
+		target: &mut Frame,
+		context: &DrawContext,
+		params: &DrawParameters,
+		matrix: [[f32; 4]; 4],
+		sampler: S,
+	) {
+		let uniforms = uniform! {
+			matrix: matrix,
+			bright_shade: self.bright_shade,
+			tex: sampler
+		};
+		target
+			.draw(
+				context.unit_quad_vertices,
+				context.unit_quad_indices,
+				&self.program,
+				&uniforms,
+				params,
+			)
+			.unwrap();
+	}
 }
 
 pub struct PictureWidget {
@@ -278,30 +307,38 @@ impl Widget for PictureWidget {
 				// Projection tranform
 				let transform = projection_transform * transform;
 
-				let sampler = texture
-					.sampled()
-					.wrap_function(gelatin::glium::uniforms::SamplerWrapFunction::Clamp);
-				let sampler = if data.img_texel_size >= 4f32 {
-					sampler.magnify_filter(gelatin::glium::uniforms::MagnifySamplerFilter::Nearest)
-				} else {
-					sampler.magnify_filter(gelatin::glium::uniforms::MagnifySamplerFilter::Linear)
-				};
-				// building the uniforms
-
-				let uniforms = uniform! {
-					matrix: Into::<[[f32; 4]; 4]>::into(transform),
-					bright_shade: data.bright_shade,
-					tex: sampler
+				let sampling = SamplerBehavior {
+					wrap_function: (
+						SamplerWrapFunction::Clamp,
+						SamplerWrapFunction::Clamp,
+						SamplerWrapFunction::Clamp,
+					),
+					magnify_filter: if data.img_texel_size >= 4f32 {
+						MagnifySamplerFilter::Nearest
+					} else {
+						MagnifySamplerFilter::Linear
+					},
+					..Default::default()
 				};
-				target
-					.draw(
-						context.unit_quad_vertices,
-						context.unit_quad_indices,
-						&data.program,
-						&uniforms,
+				let matrix = transform.into();
+				// Compressed textures are sampled directly, the GPU decompresses them
+				match &*texture {
+					ImageTexture::Srgb(texture) => data.draw_image(
+						target,
+						context,
+						&image_draw_params,
+						matrix,
+						Sampler(texture, sampling),
+					),
+					#[cfg(feature = "compressed")]
+					ImageTexture::Compressed(texture) => data.draw_image(
+						target,
+						context,
 						&image_draw_params,
-					)
-					.unwrap();
+						matrix,
+						Sampler(texture, sampling),
+					),
+				}
 			}
 		}
 		let mut borrowed = self.data.borrow_mut();
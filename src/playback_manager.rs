@@ -9,13 +9,11 @@ use rand::{thread_rng, Rng};
 
 use sys_info;
 
-use gelatin::glium;
-
 use gelatin::window::Window;
 //use crate::window::Window;
 
-use crate::image_cache;
-use crate::image_cache::ImageCache;
+use emulsion::image_cache;
+use emulsion::image_cache::{ImageCache, ImageTexture};
 
 #[derive(PartialEq)]
 pub enum LoadRequest {
@@ -48,7 +46,7 @@ pub struct PlaybackManager {
 	load_request: LoadRequest,
 
 	//should_sleep: bool,
-	image_texture: Option<Rc<glium::texture::SrgbTexture2d>>,
+	image_texture: Option<Rc<ImageTexture>>,
 	filename: Option<OsString>,
 }
 
@@ -73,7 +71,7 @@ impl PlaybackManager {
 
 		PlaybackManager {
 			playback_state: PlaybackState::Paused,
-			image_cache: ImageCache::new(cache_capaxity, thread_count),
+			image_cache: ImageCache::new(cache_capaxity, cache_capaxity / 16, thread_count),
 
 			present_remaining: Vec::new(),
 
@@ -153,7 +151,7 @@ impl PlaybackManager {
 		&self.load_request
 	}
 
-	pub fn image_texture<'a>(&'a self) -> &'a Option<Rc<glium::texture::SrgbTexture2d>> {
+	pub fn image_texture<'a>(&'a self) -> &'a Option<Rc<ImageTexture>> {
 		&self.image_texture
 	}
 
@@ -179,8 +177,8 @@ impl PlaybackManager {
 		let frame_delta_time_nanos = (NANOS_PER_SEC as f64 / framerate) as u64;
 
 		if self.playback_state == PlaybackState::Paused {
-			self.image_cache.process_prefetched(&window.display_mut()).unwrap();
-			self.image_cache.prefetch_neighbors();
+			self.image_cache.process_prefetched(&*window.display_mut()).unwrap();
+			self.image_cache.send_load_requests();
 			next_update = gelatin::NextUpdate::Latest;
 		} else if load_request == LoadRequest::None {
 			let elapsed = self.playback_start_time.elapsed();
@@ -218,7 +216,7 @@ impl PlaybackManager {
 				};
 				self.frame_count_since_playback_start += frame_step;
 			} else {
-				self.image_cache.process_prefetched(&window.display_mut()).unwrap();
+				self.image_cache.process_prefetched(&*window.display_mut()).unwrap();
 
 				let nanos_since_last = elapsed_nanos % frame_delta_time_nanos;
 				const BUISY_WAIT_TRESHOLD: f32 = 0.8;
@@ -232,7 +230,7 @@ impl PlaybackManager {
 								self.image_cache.prefetch_at_index(last);
 							}
 						}
-						_ => self.image_cache.prefetch_neighbors(),
+						_ => self.image_cache.send_load_requests(),
 					}
 				}
 			}
@@ -244,28 +242,28 @@ impl PlaybackManager {
 		//let should_sleep = load_request == LoadRequest::None && running && !update_screen;
 		// Process long operations here
 		let load_result = match load_request {
-			LoadRequest::LoadNext => Some(self.image_cache.load_next(&window.display_mut())),
-			LoadRequest::LoadPrevious => Some(self.image_cache.load_prev(&window.display_mut())),
+			LoadRequest::LoadNext => Some(self.image_cache.load_next(&*window.display_mut())),
+			LoadRequest::LoadPrevious => Some(self.image_cache.load_prev(&*window.display_mut())),
 			LoadRequest::FilePath(ref file_path) => {
 				Some(if let Some(file_name) = file_path.file_name() {
-					self.image_cache
-						.load_specific(&window.display_mut(), file_path.as_ref())
-						.map(|x| (x, OsString::from(file_name)))
+					self.image_cache.load_specific(&*window.display_mut(), file_path.as_ref()).map(
+						|(texture, dimensions)| (texture, OsString::from(file_name), dimensions),
+					)
 				} else {
 					Err(String::from("Could not extract filename").into())
 				})
 			}
 			LoadRequest::LoadAtIndex(index) => {
-				Some(self.image_cache.load_at_index(&window.display_mut(), index))
+				Some(self.image_cache.load_at_index(&*window.display_mut(), index))
 			}
 			LoadRequest::Jump(jump_count) => {
-				Some(self.image_cache.load_jump(&window.display_mut(), jump_count))
+				Some(self.image_cache.load_jump(&*window.display_mut(), jump_count))
 			}
 			LoadRequest::None => None,
 		};
 		if let Some(result) = load_result {
 			match result {
-				Ok((texture, filename)) => {
+				Ok((texture, filename, _)) => {
 					self.image_texture = Some(texture);
 					self.filename = Some(filename);
 				}